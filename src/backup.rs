@@ -0,0 +1,177 @@
+// backup.rs
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use chrono::{Duration, Local, NaiveDateTime};
+
+use crate::{names, TodoApp};
+
+/// Snapshots live in `<base_dir>/.backups/<list file stem>/<timestamp>.txt`.
+pub const BACKUP_DIR: &str = ".backups";
+
+const TIMESTAMP_FORMAT: &str = "%Y%m%d-%H%M%S%.3f";
+const DEFAULT_KEEP: usize = 20;
+
+impl TodoApp {
+    fn snapshot_dir(&self, list_name: &str) -> PathBuf {
+        self.base_dir.join(BACKUP_DIR).join(names::encode(list_name))
+    }
+
+    /// Snapshot names for a list, oldest first.
+    fn snapshots(&self, list_name: &str) -> io::Result<Vec<String>> {
+        let dir = self.snapshot_dir(list_name);
+        if !dir.is_dir() {
+            return Ok(Vec::new());
+        }
+        let mut snapshots: Vec<String> = fs::read_dir(dir)?
+            .flatten()
+            .filter_map(|entry| {
+                let name = entry.file_name().to_str()?.strip_suffix(".txt")?.to_string();
+                NaiveDateTime::parse_from_str(&name, TIMESTAMP_FORMAT).ok().map(|_| name)
+            })
+            .collect();
+        snapshots.sort();
+        Ok(snapshots)
+    }
+
+    /// Copies a list's current file into its snapshot folder before it gets
+    /// overwritten or deleted, then applies the retention policy.
+    pub(crate) fn snapshot(&self, list_name: &str) -> io::Result<()> {
+        let list_path = self.get_list_path(list_name);
+        if !list_path.exists() {
+            return Ok(());
+        }
+
+        let contents = fs::read(&list_path)?;
+        let dir = self.snapshot_dir(list_name);
+        // Nothing to keep if the newest snapshot already has these contents
+        if let Some(latest) = self.snapshots(list_name)?.last() {
+            if fs::read(dir.join(format!("{}.txt", latest)))? == contents {
+                return Ok(());
+            }
+        }
+
+        fs::create_dir_all(&dir)?;
+        let timestamp = Local::now().format(TIMESTAMP_FORMAT).to_string();
+        fs::write(dir.join(format!("{}.txt", timestamp)), contents)?;
+        self.prune_snapshots(list_name)
+    }
+
+    /// Keeps the newest `backup.keep` snapshots (20 by default) and, when
+    /// `backup.max_age_days` is set, drops any older than that.
+    fn prune_snapshots(&self, list_name: &str) -> io::Result<()> {
+        let keep = self.config
+            .get("backup.keep")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_KEEP);
+        let oldest_allowed = self.config
+            .get("backup.max_age_days")
+            .and_then(|v| v.parse::<i64>().ok())
+            .map(|days| Local::now().naive_local() - Duration::days(days));
+
+        let snapshots = self.snapshots(list_name)?;
+        let excess = snapshots.len().saturating_sub(keep);
+        for (i, snapshot) in snapshots.iter().enumerate() {
+            let too_old = oldest_allowed.is_some_and(|oldest| {
+                NaiveDateTime::parse_from_str(snapshot, TIMESTAMP_FORMAT).is_ok_and(|t| t < oldest)
+            });
+            if i < excess || too_old {
+                fs::remove_file(self.snapshot_dir(list_name).join(format!("{}.txt", snapshot)))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn list_backups(&self, list_filter: Option<&str>) -> io::Result<()> {
+        let backup_root = self.base_dir.join(BACKUP_DIR);
+        let mut lists: Vec<String> = match list_filter {
+            Some(list_name) => vec![list_name.to_string()],
+            None if backup_root.is_dir() => fs::read_dir(&backup_root)?
+                .flatten()
+                .filter_map(|entry| entry.file_name().to_str().map(names::decode))
+                .collect(),
+            None => Vec::new(),
+        };
+        lists.sort();
+
+        let mut found = false;
+        for list_name in lists {
+            let snapshots = self.snapshots(&list_name)?;
+            if snapshots.is_empty() {
+                continue;
+            }
+            found = true;
+            println!("\n📋 {}", list_name);
+            for snapshot in snapshots.iter().rev() {
+                let path = self.snapshot_dir(&list_name).join(format!("{}.txt", snapshot));
+                let tasks = fs::read_to_string(path).map(|c| c.lines().count()).unwrap_or(0);
+                println!("  {}@{}  ({} task(s))", list_name, snapshot, tasks);
+            }
+        }
+
+        if !found {
+            println!("No backups found.");
+        }
+        Ok(())
+    }
+
+    /// Restores `<list>@<timestamp>`. The list's current contents are
+    /// snapshotted first, so a restore can itself be undone.
+    pub(crate) fn restore_backup(&self, snapshot: &str) -> io::Result<()> {
+        let (list_name, timestamp) = match snapshot.rsplit_once('@') {
+            Some(parts) => parts,
+            None => {
+                println!("Error: Snapshots are named <list>@<timestamp>; see 'todo backup list'");
+                return Ok(());
+            }
+        };
+
+        if !self.snapshots(list_name)?.iter().any(|s| s == timestamp) {
+            println!("Snapshot '{}' not found.", snapshot);
+            return Ok(());
+        }
+
+        // Read it before snapshotting, whose pruning may delete this very snapshot
+        let contents = fs::read(self.snapshot_dir(list_name).join(format!("{}.txt", timestamp)))?;
+        self.snapshot(list_name)?;
+        fs::write(self.get_list_path(list_name), contents)?;
+        println!("List '{}' restored to {}", list_name, timestamp);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn restores_the_oldest_snapshot_when_at_the_cap() {
+        let base_dir = std::env::temp_dir().join(format!("todo-test-backup-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&base_dir);
+        fs::create_dir_all(&base_dir).unwrap();
+        let mut config = Config::at(base_dir.join("config")).unwrap();
+        config.set("backup.keep", "3").unwrap();
+        let app = TodoApp { base_dir: base_dir.clone(), config, project: false };
+
+        let dir = app.snapshot_dir("work");
+        fs::create_dir_all(&dir).unwrap();
+        let stamps = ["20240101-000000.000", "20240102-000000.000", "20240103-000000.000"];
+        for (i, stamp) in stamps.iter().enumerate() {
+            fs::write(dir.join(format!("{}.txt", stamp)), format!("version {}\n", i)).unwrap();
+        }
+        fs::write(app.get_list_path("work"), "current\n").unwrap();
+
+        app.restore_backup(&format!("work@{}", stamps[0])).unwrap();
+        assert_eq!(fs::read_to_string(app.get_list_path("work")).unwrap(), "version 0\n");
+        // The pre-restore contents were kept, and the cap still holds
+        let snapshots = app.snapshots("work").unwrap();
+        assert_eq!(snapshots.len(), 3);
+        let latest = dir.join(format!("{}.txt", snapshots.last().unwrap()));
+        assert_eq!(fs::read_to_string(latest).unwrap(), "current\n");
+
+        fs::remove_dir_all(base_dir).unwrap();
+    }
+}
@@ -0,0 +1,824 @@
+// caldav.rs
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+
+use crate::ical::{self, Vtodo};
+use crate::item::{self, TodoItem};
+use crate::{names, TodoApp};
+
+/// What was synced last time, per list: `<base_dir>/.caldav/<list file>`.
+pub const CALDAV_STATE_DIR: &str = ".caldav";
+
+const CALENDAR_QUERY: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<c:calendar-query xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav">
+  <d:prop><d:getetag/><c:calendar-data/></d:prop>
+  <c:filter><c:comp-filter name="VCALENDAR"><c:comp-filter name="VTODO"/></c:comp-filter></c:filter>
+</c:calendar-query>"#;
+
+/// A task on the server.
+struct Remote {
+    href: String,
+    etag: String,
+    todo: Vtodo,
+}
+
+/// What a task looked like the last time it was synced.
+#[derive(Clone)]
+struct Synced {
+    href: String,
+    uid: String,
+    etag: String,
+    fingerprint: String,
+}
+
+/// The parts of a task that are compared to tell whether it changed.
+fn fingerprint(item: &TodoItem) -> String {
+    TodoItem { depth: 0, updated: None, ..item.clone() }.to_string()
+}
+
+/// The contents of every element called `name` (ignoring namespace
+/// prefixes) in a WebDAV response. Good enough for the flat multistatus
+/// documents CalDAV servers send back.
+fn elements<'a>(xml: &'a str, name: &str) -> Vec<&'a str> {
+    let mut found = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        let tag_end = match rest.find('>') {
+            Some(end) => end,
+            None => break,
+        };
+        let tag = &rest[..tag_end];
+        let qualified = tag.split(|c: char| c.is_whitespace() || c == '/').next().unwrap_or("");
+        let local = qualified.rsplit(':').next().unwrap_or("");
+        if tag.starts_with('/') || local != name {
+            continue;
+        }
+        if tag.ends_with('/') {
+            found.push("");
+            continue;
+        }
+        let body = &rest[tag_end + 1..];
+        let close = format!("</{}>", qualified);
+        if let Some(end) = body.find(&close) {
+            found.push(&body[..end]);
+            rest = &body[end + close.len()..];
+        }
+    }
+    found
+}
+
+fn xml_text(s: &str) -> String {
+    let s = s.trim();
+    if let Some(cdata) = s.strip_prefix("<![CDATA[").and_then(|s| s.strip_suffix("]]>")) {
+        return cdata.to_string();
+    }
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let end = match rest.find(';') {
+            Some(end) => end,
+            None => break,
+        };
+        let entity = &rest[1..end];
+        let decoded = match entity {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity
+                .strip_prefix("#x")
+                .or_else(|| entity.strip_prefix("#X"))
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                .or_else(|| entity.strip_prefix('#').and_then(|dec| dec.parse().ok()))
+                .and_then(char::from_u32),
+        };
+        match decoded {
+            Some(c) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn http_error(e: ureq::Error) -> io::Error {
+    match e {
+        ureq::Error::Status(code, response) => {
+            io::Error::other(format!("{} {} ({})", code, response.status_text(), response.get_url()))
+        }
+        other => io::Error::other(other.to_string()),
+    }
+}
+
+struct Client {
+    agent: ureq::Agent,
+    base_url: String,
+    auth: Option<String>,
+}
+
+impl Client {
+    fn request(&self, method: &str, url: &str) -> ureq::Request {
+        let request = self.agent.request(method, url);
+        match &self.auth {
+            Some(auth) => request.set("Authorization", auth),
+            None => request,
+        }
+    }
+
+    fn collection_url(&self, list_name: &str) -> String {
+        format!("{}/{}/", self.base_url.trim_end_matches('/'), names::url_segment(list_name))
+    }
+
+    /// Turns an href from a response, usually an absolute path, into a URL.
+    fn resolve(&self, href: &str) -> String {
+        if href.starts_with("http://") || href.starts_with("https://") {
+            return href.to_string();
+        }
+        let after_scheme = self.base_url.find("://").map(|i| i + 3).unwrap_or(0);
+        let origin_end = self.base_url[after_scheme..]
+            .find('/')
+            .map(|i| after_scheme + i)
+            .unwrap_or(self.base_url.len());
+        format!("{}{}", &self.base_url[..origin_end], href)
+    }
+
+    /// Every VTODO in a collection, or `None` if the collection doesn't exist.
+    fn fetch(&self, collection: &str) -> io::Result<Option<Vec<Remote>>> {
+        let response = match self
+            .request("REPORT", collection)
+            .set("Depth", "1")
+            .set("Content-Type", "application/xml; charset=utf-8")
+            .send_string(CALENDAR_QUERY)
+        {
+            Ok(response) => response,
+            Err(ureq::Error::Status(404, _)) => return Ok(None),
+            Err(e) => return Err(http_error(e)),
+        };
+        let body = response.into_string()?;
+
+        let mut remotes = Vec::new();
+        for response in elements(&body, "response") {
+            let href = elements(response, "href").first().map(|h| xml_text(h)).unwrap_or_default();
+            let etag = elements(response, "getetag").first().map(|e| xml_text(e)).unwrap_or_default();
+            let data = elements(response, "calendar-data").first().map(|d| xml_text(d)).unwrap_or_default();
+            if let Some(todo) = ical::parse_vtodos(&data).into_iter().next() {
+                remotes.push(Remote { href, etag, todo });
+            }
+        }
+        Ok(Some(remotes))
+    }
+
+    fn create_collection(&self, collection: &str, list_name: &str) -> io::Result<()> {
+        let body = format!(
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<c:mkcalendar xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav">
+  <d:set><d:prop>
+    <d:displayname>{}</d:displayname>
+    <c:supported-calendar-component-set><c:comp name="VTODO"/></c:supported-calendar-component-set>
+  </d:prop></d:set>
+</c:mkcalendar>"#,
+            list_name.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+        );
+        self.request("MKCALENDAR", collection)
+            .set("Content-Type", "application/xml; charset=utf-8")
+            .send_string(&body)
+            .map_err(http_error)?;
+        Ok(())
+    }
+
+    /// Uploads a task. `etag` is what we last saw, so a change made on the
+    /// server in the meantime isn't overwritten; `None` creates a new one.
+    /// Returns false if the server refused because the task changed there.
+    fn put(&self, url: &str, ics: &str, etag: Option<&str>) -> io::Result<bool> {
+        let request = self.request("PUT", url).set("Content-Type", "text/calendar; charset=utf-8");
+        let request = match etag {
+            Some(etag) => request.set("If-Match", etag),
+            None => request.set("If-None-Match", "*"),
+        };
+        match request.send_string(ics) {
+            Ok(_) => Ok(true),
+            Err(ureq::Error::Status(412, _)) => Ok(false),
+            Err(e) => Err(http_error(e)),
+        }
+    }
+
+    /// Like `put`, returns false if the task changed on the server.
+    fn delete(&self, url: &str, etag: &str) -> io::Result<bool> {
+        match self.request("DELETE", url).set("If-Match", etag).call() {
+            Ok(_) | Err(ureq::Error::Status(404, _)) => Ok(true),
+            Err(ureq::Error::Status(412, _)) => Ok(false),
+            Err(e) => Err(http_error(e)),
+        }
+    }
+}
+
+/// Copies what the server can change into a local task, keeping its ID,
+/// position and dependencies.
+fn apply_remote(item: &mut TodoItem, remote: &TodoItem) {
+    item.text = remote.text.clone();
+    item.done = remote.done;
+    item.due = remote.due;
+    item.recur = remote.recur;
+    item.priority = remote.priority;
+    item.tags = remote.tags.clone();
+    item.note = remote.note.clone();
+}
+
+impl TodoApp {
+    fn caldav_client(&self) -> Option<Client> {
+        let base_url = self.config.get("caldav.url")?.to_string();
+        let auth = self.config.get("caldav.user").map(|user| {
+            let password = self.config.get("caldav.password").unwrap_or("");
+            format!("Basic {}", STANDARD.encode(format!("{}:{}", user, password)))
+        });
+        Some(Client { agent: ureq::Agent::new(), base_url, auth })
+    }
+
+    fn caldav_state_path(&self, list_name: &str) -> std::path::PathBuf {
+        self.base_dir.join(CALDAV_STATE_DIR).join(names::file_name(list_name))
+    }
+
+    /// Sync state keyed by local task ID. Each line is
+    /// `<id> <href> <uid> <etag> <base64 fingerprint>`, tab separated.
+    fn read_caldav_state(&self, list_name: &str) -> io::Result<HashMap<String, Synced>> {
+        let path = self.caldav_state_path(list_name);
+        let mut state = HashMap::new();
+        if !path.exists() {
+            return Ok(state);
+        }
+        for line in fs::read_to_string(path)?.lines() {
+            let fields: Vec<&str> = line.split('\t').collect();
+            if let [id, href, uid, etag, fingerprint] = fields[..] {
+                let fingerprint = STANDARD
+                    .decode(fingerprint)
+                    .ok()
+                    .and_then(|bytes| String::from_utf8(bytes).ok())
+                    .unwrap_or_default();
+                state.insert(id.to_string(), Synced {
+                    href: href.to_string(),
+                    uid: uid.to_string(),
+                    etag: etag.to_string(),
+                    fingerprint,
+                });
+            }
+        }
+        Ok(state)
+    }
+
+    fn write_caldav_state(&self, list_name: &str, state: &HashMap<String, Synced>) -> io::Result<()> {
+        let path = self.caldav_state_path(list_name);
+        fs::create_dir_all(self.base_dir.join(CALDAV_STATE_DIR))?;
+        let mut ids: Vec<&String> = state.keys().collect();
+        ids.sort();
+        let mut contents = String::new();
+        for id in ids {
+            let synced = &state[id];
+            contents.push_str(&format!(
+                "{}\t{}\t{}\t{}\t{}\n",
+                id, synced.href, synced.uid, synced.etag, STANDARD.encode(&synced.fingerprint)
+            ));
+        }
+        fs::write(path, contents)
+    }
+
+    /// Two-way sync of lists with CalDAV calendars (one per list, named
+    /// after it, under `caldav.url`). Changes made on only one side are
+    /// copied to the other; when a task changed on both, the more recently
+    /// changed copy wins and the conflict is reported.
+    pub(crate) fn caldav_sync(&self, list_name: Option<&str>) -> io::Result<()> {
+        let client = match self.caldav_client() {
+            Some(client) => client,
+            None => {
+                println!("Error: No server set; run 'todo config caldav.url <url>' (and caldav.user, caldav.password)");
+                return Ok(());
+            }
+        };
+        let lists = match list_name {
+            Some(list_name) => {
+                if !Self::check_new_list_name(list_name) {
+                    return Ok(());
+                }
+                vec![list_name.to_string()]
+            }
+            None => self.list_names()?,
+        };
+        if lists.is_empty() {
+            println!("No lists to sync.");
+        }
+        for list_name in lists {
+            self.caldav_sync_list(&client, &list_name)?;
+        }
+        Ok(())
+    }
+
+    fn caldav_sync_list(&self, client: &Client, list_name: &str) -> io::Result<()> {
+        let collection = client.collection_url(list_name);
+        let remotes = match client.fetch(&collection)? {
+            Some(remotes) => remotes,
+            None => {
+                client.create_collection(&collection, list_name)?;
+                Vec::new()
+            }
+        };
+        let mut items = self.read_items(list_name)?;
+        let state = self.read_caldav_state(list_name)?;
+        let remote_by_href: HashMap<&str, &Remote> = remotes.iter().map(|r| (r.href.as_str(), r)).collect();
+
+        let uid_of = |item: &TodoItem| state.get(&item.id).map(|s| s.uid.clone()).unwrap_or_else(|| ical::uid(list_name, &item.id));
+        let href_of = |item: &TodoItem| {
+            state.get(&item.id).map(|s| s.href.clone()).unwrap_or_else(|| format!("{}{}.ics", collection, names::url_segment(&item.id)))
+        };
+
+        let mut uploads: Vec<(usize, Option<String>)> = Vec::new();
+        let mut deletes: Vec<(String, String)> = Vec::new();
+        let mut removed_here: Vec<usize> = Vec::new();
+        let mut conflicts: Vec<String> = Vec::new();
+        let (mut downloaded, mut deleted_there) = (0, 0);
+
+        // Tasks we have
+        for (index, item) in items.iter_mut().enumerate() {
+            let synced = match state.get(&item.id) {
+                Some(synced) => synced,
+                None => {
+                    uploads.push((index, None));
+                    continue;
+                }
+            };
+            let changed_here = fingerprint(item) != synced.fingerprint;
+            match remote_by_href.get(synced.href.as_str()) {
+                None if changed_here => {
+                    conflicts.push(format!("'{}' was deleted on the server but changed here; uploaded it again", item.text));
+                    uploads.push((index, None));
+                }
+                None => removed_here.push(index),
+                Some(remote) => {
+                    let changed_there = remote.etag != synced.etag;
+                    match (changed_here, changed_there) {
+                        (false, false) => {}
+                        (true, false) => uploads.push((index, Some(remote.etag.clone()))),
+                        (false, true) => {
+                            apply_remote(item, &remote.todo.item);
+                            downloaded += 1;
+                        }
+                        (true, true) => {
+                            if remote.todo.modified > item.updated {
+                                conflicts.push(format!("'{}' changed in both places; kept the server's version", item.text));
+                                apply_remote(item, &remote.todo.item);
+                                downloaded += 1;
+                            } else {
+                                conflicts.push(format!("'{}' changed in both places; kept this version", item.text));
+                                uploads.push((index, Some(remote.etag.clone())));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        // A task deleted on the server goes here with its subtasks, unless
+        // one of them changed here, in which case it's uploaded again
+        let mut removed_ranges: Vec<(usize, usize)> = Vec::new();
+        for &index in &removed_here {
+            if removed_ranges.last().is_some_and(|&(_, end)| index < end) {
+                continue;
+            }
+            let end = item::subtree_end(&items, index);
+            if uploads.iter().any(|&(i, _)| i > index && i < end) {
+                conflicts.push(format!("'{}' was deleted on the server but its subtasks changed here; uploaded it again", items[index].text));
+                uploads.push((index, None));
+                continue;
+            }
+            removed_ranges.push((index, end));
+        }
+
+        // Tasks only the server has: new there, or deleted here
+        let known_hrefs: HashMap<&str, &Synced> = state.values().map(|s| (s.href.as_str(), s)).collect();
+        let local_ids: HashSet<&str> = items
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| !removed_ranges.iter().any(|&(start, end)| (start..end).contains(index)))
+            .map(|(_, item)| item.id.as_str())
+            .collect();
+        let mut new_remote: Vec<&Remote> = Vec::new();
+        for remote in &remotes {
+            let synced = known_hrefs.get(remote.href.as_str());
+            let id = state.iter().find(|(_, s)| s.href == remote.href).map(|(id, _)| id.as_str());
+            if id.is_some_and(|id| local_ids.contains(id)) {
+                continue;
+            }
+            match synced {
+                Some(synced) if synced.etag == remote.etag => {
+                    deletes.push((client.resolve(&remote.href), remote.etag.clone()));
+                    deleted_there += 1;
+                }
+                Some(_) => {
+                    conflicts.push(format!("'{}' was deleted here but changed on the server; restored it", remote.todo.item.text));
+                    new_remote.push(remote);
+                }
+                None => new_remote.push(remote),
+            }
+        }
+
+        // Apply everything locally first, so the uploads see final positions
+        let mut new_state: HashMap<String, Synced> = HashMap::new();
+        let mut removed = Vec::new();
+        for &(start, end) in removed_ranges.iter().rev() {
+            removed.extend(items.drain(start..end));
+            for (i, _) in uploads.iter_mut().filter(|(i, _)| *i >= end) {
+                *i -= end - start;
+            }
+        }
+        let mut upload_ids: Vec<(String, Option<String>)> = uploads
+            .iter()
+            .map(|(index, etag)| (items[*index].id.clone(), etag.clone()))
+            .collect();
+        for remote in &new_remote {
+            let mut item = TodoItem { id: item::new_id(), updated: remote.todo.modified, ..remote.todo.item.clone() };
+            if item.created.is_none() {
+                item.created = Some(crate::recur::today());
+            }
+            let parent = remote.todo.parent.as_deref().and_then(|parent_uid| {
+                items.iter().position(|i| uid_of(i) == parent_uid || i.id == parent_uid)
+            });
+            match parent {
+                Some(parent) => {
+                    item.depth = items[parent].depth + 1;
+                    let end = item::subtree_end(&items, parent);
+                    items.insert(end, item.clone());
+                }
+                None => items.push(item.clone()),
+            }
+            new_state.insert(item.id.clone(), Synced {
+                href: remote.href.clone(),
+                uid: remote.todo.uid.clone(),
+                etag: remote.etag.clone(),
+                fingerprint: String::new(),
+            });
+        }
+        for (id, synced) in &state {
+            new_state.entry(id.clone()).or_insert(synced.clone());
+        }
+
+        let local_changes = downloaded > 0 || !removed.is_empty() || !new_remote.is_empty();
+        if local_changes {
+            item::roll_up(&mut items);
+            self.write_items(list_name, &items)?;
+            self.record_deleted(&removed)?;
+            items = self.read_items(list_name)?;
+        }
+
+        // Then push our changes to the server
+        let parent_of = item::parents(&items);
+        let mut refused: HashSet<String> = HashSet::new();
+        upload_ids.retain(|(id, _)| items.iter().any(|item| &item.id == id));
+        for (id, etag) in &upload_ids {
+            let index = items.iter().position(|item| &item.id == id).unwrap();
+            let item = &items[index];
+            let parent_uid = parent_of[index].map(|p| {
+                new_state.get(&items[p].id).map(|s| s.uid.clone()).unwrap_or_else(|| ical::uid(list_name, &items[p].id))
+            });
+            let href = new_state.get(id).map(|s| s.href.clone()).unwrap_or_else(|| href_of(item));
+            let uid = new_state.get(id).map(|s| s.uid.clone()).unwrap_or_else(|| ical::uid(list_name, id));
+            let ics = ical::calendar(None, &[ical::vtodo(item, &uid, parent_uid.as_deref())]);
+            // A task restored after the server deleted it is created afresh
+            let etag = etag.as_deref().filter(|_| remote_by_href.contains_key(href.as_str()));
+            if client.put(&client.resolve(&href), &ics, etag)? {
+                new_state.insert(id.clone(), Synced { href, uid, etag: String::new(), fingerprint: String::new() });
+            } else {
+                conflicts.push(format!("'{}' changed on the server during the sync; sync again to resolve", item.text));
+                refused.insert(id.clone());
+            }
+        }
+        for (url, etag) in &deletes {
+            if !client.delete(url, etag)? {
+                conflicts.push(format!("{} changed on the server during the sync; it will come back next time", url));
+                deleted_there -= 1;
+            }
+        }
+
+        // Record what both sides look like now, matching by UID since the
+        // server may spell new tasks' hrefs differently
+        let remotes = client.fetch(&collection)?.unwrap_or_default();
+        let by_uid: HashMap<&str, &Remote> = remotes.iter().map(|r| (r.todo.uid.as_str(), r)).collect();
+        let mut state = HashMap::new();
+        for item in &items {
+            // Keep what we knew before for refused uploads, so they are
+            // seen as changed on both sides next time
+            if refused.contains(&item.id) {
+                if let Some(synced) = new_state.remove(&item.id) {
+                    state.insert(item.id.clone(), synced);
+                }
+                continue;
+            }
+            let uid = new_state.get(&item.id).map(|s| s.uid.clone()).unwrap_or_else(|| ical::uid(list_name, &item.id));
+            if let Some(remote) = by_uid.get(uid.as_str()) {
+                state.insert(item.id.clone(), Synced {
+                    href: remote.href.clone(),
+                    uid,
+                    etag: remote.etag.clone(),
+                    fingerprint: fingerprint(item),
+                });
+            }
+        }
+        self.write_caldav_state(list_name, &state)?;
+
+        println!(
+            "Synced '{}': {} uploaded, {} downloaded, {} deleted here, {} deleted on the server",
+            list_name,
+            upload_ids.len() - refused.len(),
+            downloaded + new_remote.len(),
+            removed.len(),
+            deleted_there
+        );
+        for conflict in &conflicts {
+            println!("  Conflict: {}", conflict);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::sync::{Arc, Mutex};
+    use std::thread::{self, JoinHandle};
+
+    use tiny_http::{Response, Server};
+
+    use super::*;
+    use crate::config::Config;
+
+    /// What the stand-in server holds: calendar paths, and each resource's
+    /// ETag and contents by path.
+    #[derive(Default)]
+    struct Calendars {
+        collections: HashSet<String>,
+        resources: BTreeMap<String, (String, String)>,
+        next_etag: usize,
+    }
+
+    impl Calendars {
+        fn store(&mut self, path: &str, ics: String) {
+            self.next_etag += 1;
+            self.resources.insert(path.to_string(), (format!("\"{}\"", self.next_etag), ics));
+        }
+
+        /// The paths of the resources whose contents contain `text`.
+        fn find(&self, text: &str) -> Vec<String> {
+            self.resources.iter().filter(|(_, (_, ics))| ics.contains(text)).map(|(path, _)| path.clone()).collect()
+        }
+    }
+
+    /// A CalDAV server just big enough for the client: REPORT, MKCALENDAR,
+    /// and conditional PUT and DELETE.
+    struct StandIn {
+        server: Arc<Server>,
+        calendars: Arc<Mutex<Calendars>>,
+        thread: Option<JoinHandle<()>>,
+    }
+
+    impl StandIn {
+        fn start() -> Self {
+            let server = Arc::new(Server::http("127.0.0.1:0").unwrap());
+            let calendars = Arc::new(Mutex::new(Calendars::default()));
+            let thread = {
+                let (server, calendars) = (server.clone(), calendars.clone());
+                thread::spawn(move || {
+                    for mut request in server.incoming_requests() {
+                        let header = |name: &str| {
+                            request.headers().iter().find(|h| h.field.as_str().as_str().eq_ignore_ascii_case(name)).map(|h| h.value.to_string())
+                        };
+                        let (if_match, if_none_match) = (header("If-Match"), header("If-None-Match"));
+                        let mut body = String::new();
+                        request.as_reader().read_to_string(&mut body).unwrap();
+                        let path = request.url().to_string();
+                        let method = request.method().as_str().to_string();
+
+                        let mut calendars = calendars.lock().unwrap();
+                        let current = calendars.resources.get(&path).map(|(etag, _)| etag.clone());
+                        let precondition_failed = match (&current, &if_match, &if_none_match) {
+                            (_, Some(wanted), _) => current.as_ref() != Some(wanted),
+                            (Some(_), None, Some(_)) => true,
+                            _ => false,
+                        };
+                        let response = match method.as_str() {
+                            "REPORT" if !calendars.collections.contains(&path) => Response::from_string("").with_status_code(404),
+                            "REPORT" => {
+                                let mut xml = String::from(r#"<d:multistatus xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav">"#);
+                                for (href, (etag, ics)) in calendars.resources.range(path.clone()..) {
+                                    if !href.starts_with(&path) {
+                                        break;
+                                    }
+                                    let ics = ics.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;");
+                                    xml.push_str(&format!(
+                                        "<d:response><d:href>{}</d:href><d:propstat><d:prop><d:getetag>{}</d:getetag>\
+                                         <c:calendar-data>{}</c:calendar-data></d:prop></d:propstat></d:response>",
+                                        href,
+                                        etag.replace('"', "&quot;"),
+                                        ics
+                                    ));
+                                }
+                                xml.push_str("</d:multistatus>");
+                                Response::from_string(xml).with_status_code(207)
+                            }
+                            "MKCALENDAR" => {
+                                calendars.collections.insert(path);
+                                Response::from_string("").with_status_code(201)
+                            }
+                            "PUT" | "DELETE" if precondition_failed => Response::from_string("").with_status_code(412),
+                            "PUT" => {
+                                calendars.store(&path, body);
+                                Response::from_string("").with_status_code(if current.is_some() { 204 } else { 201 })
+                            }
+                            "DELETE" => {
+                                calendars.resources.remove(&path);
+                                Response::from_string("").with_status_code(204)
+                            }
+                            _ => Response::from_string("").with_status_code(405),
+                        };
+                        drop(calendars);
+                        let _ = request.respond(response);
+                    }
+                })
+            };
+            StandIn { server, calendars, thread: Some(thread) }
+        }
+
+        fn url(&self) -> String {
+            format!("http://{}/calendars/me", self.server.server_addr().to_ip().unwrap())
+        }
+    }
+
+    impl Drop for StandIn {
+        fn drop(&mut self) {
+            self.server.unblock();
+            if let Some(thread) = self.thread.take() {
+                let _ = thread.join();
+            }
+        }
+    }
+
+    /// An app with its own lists and config in a fresh temporary directory.
+    fn app(name: &str, url: &str) -> TodoApp {
+        let base_dir = std::env::temp_dir().join(format!("todo-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&base_dir);
+        fs::create_dir_all(&base_dir).unwrap();
+        let mut config = Config::at(base_dir.join("config")).unwrap();
+        config.set("caldav.url", url).unwrap();
+        TodoApp { base_dir, config, project: false }
+    }
+
+    fn texts(app: &TodoApp, list_name: &str) -> Vec<String> {
+        app.read_items(list_name).unwrap().iter().map(|item| format!("{}{}", "  ".repeat(item.depth), item.text)).collect()
+    }
+
+    #[test]
+    fn pushes_and_pulls_through_a_caldav_server() {
+        let server = StandIn::start();
+        let app = app("caldav", &server.url());
+        let collection = "/calendars/me/work/";
+
+        // Push: the calendar is created and every task uploaded
+        let mut items = vec![TodoItem::new("write report".to_string()), TodoItem::new("outline".to_string())];
+        items[1].depth = 1;
+        app.write_items("work", &items).unwrap();
+        app.caldav_sync(Some("work")).unwrap();
+        {
+            let calendars = server.calendars.lock().unwrap();
+            assert!(calendars.collections.contains(collection));
+            assert_eq!(calendars.resources.len(), 2);
+            assert_eq!(calendars.find("SUMMARY:write report").len(), 1);
+            assert_eq!(calendars.find("UID:work-").len(), 2);
+            let subtask = &calendars.resources[&calendars.find("SUMMARY:outline")[0]].1;
+            assert!(subtask.contains("RELATED-TO;RELTYPE=PARENT:"));
+        }
+
+        // Nothing changed on either side: nothing moves
+        let before: Vec<String> = server.calendars.lock().unwrap().resources.values().map(|(etag, _)| etag.clone()).collect();
+        app.caldav_sync(Some("work")).unwrap();
+        let after: Vec<String> = server.calendars.lock().unwrap().resources.values().map(|(etag, _)| etag.clone()).collect();
+        assert_eq!(before, after);
+
+        // Pull: an edit and a new task made on the server come down
+        {
+            let mut calendars = server.calendars.lock().unwrap();
+            let path = calendars.find("SUMMARY:outline")[0].clone();
+            let ics = calendars.resources[&path].1.replace("SUMMARY:outline", "SUMMARY:outline it").replace("NEEDS-ACTION", "COMPLETED");
+            calendars.store(&path, ics);
+            let phone = ical::calendar(None, &[ical::vtodo(&TodoItem::new("call back".to_string()), "from-phone", None)]);
+            calendars.store(&format!("{}from-phone.ics", collection), phone);
+        }
+        app.caldav_sync(Some("work")).unwrap();
+        assert_eq!(texts(&app, "work"), ["write report", "  outline it", "call back"]);
+        assert!(app.read_items("work").unwrap()[1].done);
+
+        // Local edits go up, and a task removed here is deleted there
+        let mut items = app.read_items("work").unwrap();
+        items[0].text = "write the report".to_string();
+        items.remove(2);
+        app.write_items("work", &items).unwrap();
+        app.caldav_sync(Some("work")).unwrap();
+        {
+            let calendars = server.calendars.lock().unwrap();
+            assert_eq!(calendars.resources.len(), 2);
+            assert_eq!(calendars.find("SUMMARY:write the report").len(), 1);
+            assert!(calendars.find("call back").is_empty());
+        }
+
+        // A task deleted on the server is removed here
+        {
+            let mut calendars = server.calendars.lock().unwrap();
+            let path = calendars.find("SUMMARY:outline it")[0].clone();
+            calendars.resources.remove(&path);
+        }
+        app.caldav_sync(Some("work")).unwrap();
+        assert_eq!(texts(&app, "work"), ["write the report"]);
+
+        // ...together with its subtasks, which go from the server as well
+        let mut items = app.read_items("work").unwrap();
+        for (text, depth) in [("gather data", 1), ("ask finance", 2), ("print it", 0)] {
+            let mut item = TodoItem::new(text.to_string());
+            item.depth = depth;
+            items.push(item);
+        }
+        app.write_items("work", &items).unwrap();
+        app.caldav_sync(Some("work")).unwrap();
+        assert_eq!(server.calendars.lock().unwrap().resources.len(), 4);
+        {
+            let mut calendars = server.calendars.lock().unwrap();
+            let path = calendars.find("SUMMARY:write the report")[0].clone();
+            calendars.resources.remove(&path);
+        }
+        app.caldav_sync(Some("work")).unwrap();
+        assert_eq!(texts(&app, "work"), ["print it"]);
+        assert_eq!(server.calendars.lock().unwrap().resources.len(), 1);
+        app.caldav_sync(Some("work")).unwrap();
+        assert_eq!(texts(&app, "work"), ["print it"]);
+
+        let _ = fs::remove_dir_all(&app.base_dir);
+    }
+
+    #[test]
+    fn keeps_the_newer_side_of_a_conflict() {
+        let server = StandIn::start();
+        let app = app("caldav-conflict", &server.url());
+        app.write_items("home", &[TodoItem::new("water plants".to_string())]).unwrap();
+        app.caldav_sync(Some("home")).unwrap();
+
+        // Changed on the server long ago, and here just now: ours wins
+        {
+            let mut calendars = server.calendars.lock().unwrap();
+            let path = calendars.find("SUMMARY:water plants")[0].clone();
+            let ics: String = calendars.resources[&path]
+                .1
+                .lines()
+                .map(|line| if line.starts_with("LAST-MODIFIED:") { "LAST-MODIFIED:20000101T000000Z" } else { line })
+                .map(|line| format!("{}\r\n", line.replace("water plants", "water the plants")))
+                .collect();
+            calendars.store(&path, ics);
+        }
+        let mut items = app.read_items("home").unwrap();
+        items[0].text = "water the cactus".to_string();
+        app.write_items("home", &items).unwrap();
+        app.caldav_sync(Some("home")).unwrap();
+
+        assert_eq!(texts(&app, "home"), ["water the cactus"]);
+        assert_eq!(server.calendars.lock().unwrap().find("SUMMARY:water the cactus").len(), 1);
+
+        // Deleted on the server while a subtask changed here: both stay
+        let mut items = app.read_items("home").unwrap();
+        let mut subtask = TodoItem::new("buy a can".to_string());
+        subtask.depth = 1;
+        items.push(subtask);
+        app.write_items("home", &items).unwrap();
+        app.caldav_sync(Some("home")).unwrap();
+        {
+            let mut calendars = server.calendars.lock().unwrap();
+            let path = calendars.find("SUMMARY:water the cactus")[0].clone();
+            calendars.resources.remove(&path);
+        }
+        let mut items = app.read_items("home").unwrap();
+        items[1].text = "buy a watering can".to_string();
+        app.write_items("home", &items).unwrap();
+        app.caldav_sync(Some("home")).unwrap();
+
+        assert_eq!(texts(&app, "home"), ["water the cactus", "  buy a watering can"]);
+        assert_eq!(server.calendars.lock().unwrap().find("SUMMARY:water the cactus").len(), 1);
+        let _ = fs::remove_dir_all(&app.base_dir);
+    }
+}
@@ -0,0 +1,135 @@
+// clipboard.rs
+
+use std::env;
+use std::fs::OpenOptions;
+use std::io::{self, IsTerminal, Write};
+use std::process::{Command, Stdio};
+
+use base64::Engine;
+
+/// A way of getting text onto the user's clipboard.
+enum Backend {
+    /// An external program that reads the text on stdin.
+    Program { name: String, program: String, args: Vec<String> },
+    /// The OSC 52 terminal escape sequence, which lets the terminal emulator
+    /// set the clipboard and so also works over SSH.
+    Osc52,
+}
+
+impl Backend {
+    fn program(name: &str, args: &[&str]) -> Self {
+        Backend::Program {
+            name: name.to_string(),
+            program: name.to_string(),
+            args: args.iter().map(|a| a.to_string()).collect(),
+        }
+    }
+
+    fn name(&self) -> &str {
+        match self {
+            Backend::Program { name, .. } => name,
+            Backend::Osc52 => "OSC 52",
+        }
+    }
+
+    fn copy(&self, text: &str) -> bool {
+        match self {
+            Backend::Program { program, args, .. } => {
+                let child = Command::new(program)
+                    .args(args)
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .spawn();
+                let mut child = match child {
+                    Ok(child) => child,
+                    Err(_) => return false,
+                };
+                if let Some(mut stdin) = child.stdin.take() {
+                    if stdin.write_all(text.as_bytes()).is_err() {
+                        let _ = child.kill();
+                        let _ = child.wait();
+                        return false;
+                    }
+                }
+                matches!(child.wait(), Ok(status) if status.success())
+            }
+            Backend::Osc52 => write_osc52(text).is_ok(),
+        }
+    }
+}
+
+fn write_osc52(text: &str) -> io::Result<()> {
+    let encoded = base64::engine::general_purpose::STANDARD.encode(text);
+    let mut sequence = format!("\x1b]52;c;{}\x07", encoded);
+    // tmux swallows OSC 52 unless it is wrapped in a passthrough sequence
+    if env::var_os("TMUX").is_some() {
+        sequence = format!("\x1bPtmux;{}\x1b\\", sequence.replace('\x1b', "\x1b\x1b"));
+    }
+
+    match OpenOptions::new().write(true).open("/dev/tty") {
+        Ok(mut tty) => {
+            tty.write_all(sequence.as_bytes())?;
+            tty.flush()
+        }
+        Err(_) if io::stdout().is_terminal() => {
+            let mut out = io::stdout();
+            out.write_all(sequence.as_bytes())?;
+            out.flush()
+        }
+        Err(e) => Err(e),
+    }
+}
+
+fn is_ssh_session() -> bool {
+    env::var_os("SSH_TTY").is_some() || env::var_os("SSH_CONNECTION").is_some()
+}
+
+/// Works out which backends to try, in order. `configured` is the
+/// `clipboard` config value: `none` disables copying, `osc52` forces the
+/// escape sequence and anything else is run as a shell command.
+fn backends(configured: Option<&str>) -> Vec<Backend> {
+    match configured.map(str::trim) {
+        Some("none") => return Vec::new(),
+        Some("osc52") => return vec![Backend::Osc52],
+        Some(command) if !command.is_empty() => {
+            return vec![Backend::Program {
+                name: command.to_string(),
+                program: "sh".to_string(),
+                args: vec!["-c".to_string(), command.to_string()],
+            }];
+        }
+        _ => {}
+    }
+
+    let mut backends = Vec::new();
+    if is_ssh_session() {
+        backends.push(Backend::Osc52);
+    }
+    if env::var_os("WAYLAND_DISPLAY").is_some() {
+        backends.push(Backend::program("wl-copy", &[]));
+    }
+    if env::var_os("DISPLAY").is_some() {
+        backends.push(Backend::program("xsel", &["--clipboard", "--input"]));
+        backends.push(Backend::program("xclip", &["-selection", "clipboard"]));
+    }
+    if cfg!(target_os = "macos") {
+        backends.push(Backend::program("pbcopy", &[]));
+    }
+    if cfg!(windows) {
+        backends.push(Backend::program("clip", &[]));
+    }
+    if !is_ssh_session() && io::stdout().is_terminal() {
+        backends.push(Backend::Osc52);
+    }
+    backends
+}
+
+/// Copies `text` to the clipboard, returning the name of the backend that
+/// succeeded, or `None` if every backend failed.
+pub fn copy(text: &str, configured: Option<&str>) -> Option<String> {
+    backends(configured)
+        .into_iter()
+        .find(|backend| backend.copy(text))
+        .map(|backend| backend.name().to_string())
+}
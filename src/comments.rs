@@ -0,0 +1,134 @@
+// comments.rs
+
+use std::path::{Path, PathBuf};
+
+/// What starts a comment in the languages we know, checked anywhere before
+/// the keyword so trailing comments count too.
+const COMMENT_OPENERS: &[&str] = &["//", "/*", "#", "--", ";", "<!--", "%"];
+
+/// A TODO-style comment found in source text.
+pub struct Comment {
+    /// 1-based, as editors and compilers show it.
+    pub line: usize,
+    pub keyword: &'static str,
+    pub text: String,
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn in_comment(before: &str) -> bool {
+    // Lines inside a /* ... */ block usually start with '*'
+    before.trim_start().starts_with('*') || COMMENT_OPENERS.iter().any(|opener| before.contains(opener))
+}
+
+/// Finds `keyword` as a whole word in a comment on `line`, returning where
+/// it starts.
+fn find_keyword(line: &str, keyword: &str) -> Option<usize> {
+    line.match_indices(keyword).map(|(start, _)| start).find(|&start| {
+        let before = &line[..start];
+        let after = &line[start + keyword.len()..];
+        !before.ends_with(is_word_char)
+            && !after.starts_with(is_word_char)
+            && in_comment(before)
+    })
+}
+
+/// The comment's text after the keyword, without an `(author)` part, the
+/// colon or a block comment's closing marker.
+fn comment_text(after: &str) -> String {
+    let mut text = after;
+    if text.starts_with('(') {
+        if let Some(end) = text.find(')') {
+            text = &text[end + 1..];
+        }
+    }
+    let text = text.trim_start_matches(':').trim();
+    let text = text.strip_suffix("*/").or_else(|| text.strip_suffix("-->")).unwrap_or(text);
+    text.trim().to_string()
+}
+
+/// Finds the comments in `source` that start with one of `keywords`, such
+/// as `// TODO: ...` or `# FIXME(sam) ...`. Comments without any text after
+/// the keyword are left out, as there's nothing to make a task of.
+pub fn find(source: &str, keywords: &[&'static str]) -> Vec<Comment> {
+    let mut comments = Vec::new();
+    for (index, line) in source.lines().enumerate() {
+        let found = keywords
+            .iter()
+            .filter_map(|&keyword| find_keyword(line, keyword).map(|start| (start, keyword)))
+            .min_by_key(|(start, _)| *start);
+        if let Some((start, keyword)) = found {
+            let text = comment_text(&line[start + keyword.len()..]);
+            if !text.is_empty() {
+                comments.push(Comment { line: index + 1, keyword, text });
+            }
+        }
+    }
+    comments
+}
+
+/// The tag recording where a comment is: `<file>:<line>`.
+pub fn location_tag(file: &Path, line: usize) -> PathBuf {
+    PathBuf::from(format!("{}:{}", file.display(), line))
+}
+
+/// Splits a `<file>:<line>` tag, if that's what it is.
+pub fn parse_location(tag: &Path) -> Option<(PathBuf, usize)> {
+    let tag = tag.to_str()?;
+    let (file, line) = tag.rsplit_once(':')?;
+    match line.parse() {
+        Ok(line) if line > 0 => Some((PathBuf::from(file), line)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(source: &str) -> Vec<(usize, &'static str, String)> {
+        find(source, &["TODO", "FIXME"]).into_iter().map(|c| (c.line, c.keyword, c.text)).collect()
+    }
+
+    #[test]
+    fn finds_comments_in_many_languages() {
+        let source = "\
+// TODO: rust or c
+let x = 1; # FIXME(sam): trailing
+/* TODO wrap it */
+ * TODO inside a block
+-- TODO: sql
+<!-- TODO: html -->";
+        assert_eq!(texts(source), [
+            (1, "TODO", "rust or c".to_string()),
+            (2, "FIXME", "trailing".to_string()),
+            (3, "TODO", "wrap it".to_string()),
+            (4, "TODO", "inside a block".to_string()),
+            (5, "TODO", "sql".to_string()),
+            (6, "TODO", "html".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn ignores_code_words_and_empty_comments() {
+        assert!(texts("let TODO = 1;").is_empty());
+        assert!(texts("// TODOS are listed here").is_empty());
+        assert!(texts("// MY_TODO: not a keyword").is_empty());
+        assert!(texts("// TODO:").is_empty());
+        // The earliest keyword on the line wins
+        assert_eq!(texts("// FIXME then TODO"), [(1, "FIXME", "then TODO".to_string())]);
+    }
+
+    #[test]
+    fn parses_locations() {
+        let tag = location_tag(Path::new("/src/main.rs"), 12);
+        assert_eq!(parse_location(&tag), Some((PathBuf::from("/src/main.rs"), 12)));
+        // Only the last colon separates the line
+        assert_eq!(parse_location(Path::new("C:/code/a:b.rs:3")), Some((PathBuf::from("C:/code/a:b.rs"), 3)));
+        assert_eq!(parse_location(Path::new("/src/main.rs")), None);
+        assert_eq!(parse_location(Path::new("/src/main.rs:0")), None);
+        assert_eq!(parse_location(Path::new("commit:abc123")), None);
+    }
+}
@@ -0,0 +1,144 @@
+// config.rs
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+pub const CONFIG_FILE: &str = "config";
+
+/// Simple `key = value` settings stored in the user's config directory
+/// (`~/.config/todo/config` on Linux), or at `$TODO_CONFIG` when set.
+pub struct Config {
+    path: PathBuf,
+    values: BTreeMap<String, String>,
+    /// A project's own settings, laid over these; see `with_project`.
+    project: Option<Box<Config>>,
+}
+
+/// Whether `key` may come from a project's config file. Project files
+/// arrive with cloned code, so this excludes anything that runs commands
+/// (`clipboard`) or reaches other machines (`caldav.*`, `sync.*`).
+pub fn is_project_key(key: &str) -> bool {
+    key.starts_with("sort.")
+}
+
+impl Config {
+    pub fn load() -> io::Result<Self> {
+        let path = match env::var_os("TODO_CONFIG") {
+            Some(path) => PathBuf::from(path),
+            None => dirs::config_dir()
+                .unwrap_or_else(|| PathBuf::from("."))
+                .join("todo")
+                .join(CONFIG_FILE),
+        };
+        Self::at(path)
+    }
+
+    /// Settings stored in the file at `path`.
+    pub fn at(path: PathBuf) -> io::Result<Self> {
+        let mut values = BTreeMap::new();
+        if path.exists() {
+            for line in fs::read_to_string(&path)?.lines() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                if let Some((key, value)) = line.split_once('=') {
+                    values.insert(key.trim().to_string(), value.trim().to_string());
+                }
+            }
+        }
+        Ok(Config { path, values, project: None })
+    }
+
+    /// These settings with the project ones from the file at `path` laid
+    /// over them. Only keys passing `is_project_key` are read from or
+    /// written to that file; the rest stay with these settings.
+    pub fn with_project(mut self, path: PathBuf) -> io::Result<Self> {
+        let mut project = Self::at(path)?;
+        project.values.retain(|key, _| is_project_key(key));
+        self.project = Some(Box::new(project));
+        Ok(self)
+    }
+
+    /// The file `key` is read from and written to.
+    fn layer(&self, key: &str) -> &Config {
+        match &self.project {
+            Some(project) if is_project_key(key) => project,
+            _ => self,
+        }
+    }
+
+    fn layer_mut(&mut self, key: &str) -> &mut Config {
+        if !is_project_key(key) || self.project.is_none() {
+            return self;
+        }
+        self.project.as_deref_mut().expect("checked above")
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.layer(key).values.get(key).map(|v| v.as_str())
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = (&String, &String)> {
+        let mut entries: BTreeMap<&String, &String> = self.values.iter().collect();
+        if let Some(project) = &self.project {
+            entries.retain(|key, _| !is_project_key(key));
+            entries.extend(project.values.iter());
+        }
+        entries.into_iter()
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> io::Result<()> {
+        let layer = self.layer_mut(key);
+        layer.values.insert(key.to_string(), value.to_string());
+        layer.save()
+    }
+
+    pub fn unset(&mut self, key: &str) -> io::Result<()> {
+        let layer = self.layer_mut(key);
+        layer.values.remove(key);
+        layer.save()
+    }
+
+    fn save(&self) -> io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut contents = String::new();
+        for (key, value) in &self.values {
+            contents.push_str(&format!("{} = {}\n", key, value));
+        }
+        fs::write(&self.path, contents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn project_files_only_supply_project_keys() {
+        let dir = env::temp_dir().join(format!("todo-test-config-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("global"), "clipboard = xclip\nsort.work = due\n").unwrap();
+        fs::write(dir.join("project"), "clipboard = rm -rf ~\nsort.work = priority\n").unwrap();
+
+        let mut config = Config::at(dir.join("global")).unwrap().with_project(dir.join("project")).unwrap();
+        assert_eq!(config.get("clipboard"), Some("xclip"));
+        assert_eq!(config.get("sort.work"), Some("priority"));
+        let entries: Vec<_> = config.entries().map(|(k, v)| format!("{} = {}", k, v)).collect();
+        assert_eq!(entries, ["clipboard = xclip", "sort.work = priority"]);
+
+        // Writes go to the file the key is read from
+        config.set("sort.home", "text").unwrap();
+        config.set("caldav.url", "https://example.com").unwrap();
+        assert_eq!(fs::read_to_string(dir.join("project")).unwrap(), "sort.home = text\nsort.work = priority\n");
+        assert!(fs::read_to_string(dir.join("global")).unwrap().contains("caldav.url = https://example.com"));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,191 @@
+// deps.rs
+
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+
+use crate::item::{self, TodoItem};
+use crate::names;
+
+/// A reference to a task in some list, written `<list>:<id>`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TaskRef {
+    pub list: String,
+    pub id: String,
+}
+
+impl TaskRef {
+    pub fn parse(s: &str) -> Option<Self> {
+        let (list, id) = s.trim().rsplit_once(':')?;
+        if list.is_empty() || id.is_empty() {
+            return None;
+        }
+        Some(TaskRef { list: names::normalize(&names::decode(list)), id: id.to_string() })
+    }
+}
+
+impl fmt::Display for TaskRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Encoded so list names can't break the `[[ DEPENDS : ... ]]` block
+        write!(f, "{}:{}", names::encode(&self.list), self.id)
+    }
+}
+
+/// Every list's tasks, keyed by list name.
+pub type Lists = BTreeMap<String, Vec<TodoItem>>;
+
+fn find<'a>(lists: &'a Lists, task: &TaskRef) -> Option<&'a TodoItem> {
+    lists.get(&task.list)?.iter().find(|item| item.id == task.id)
+}
+
+/// Prerequisites of `items[index]` (or of any of its parents) that are still
+/// open. Prerequisites that no longer exist don't block anything.
+pub fn open_prerequisites(lists: &Lists, items: &[TodoItem], index: usize) -> Vec<TaskRef> {
+    let mut depth = items[index].depth + 1;
+    let mut open = Vec::new();
+    for item in items[..=index].iter().rev() {
+        if item.depth >= depth {
+            continue;
+        }
+        depth = item.depth;
+        for dep in &item.depends {
+            if find(lists, dep).is_some_and(|prerequisite| !prerequisite.done) {
+                open.push(dep.clone());
+            }
+        }
+        if depth == 0 {
+            break;
+        }
+    }
+    open
+}
+
+pub fn is_blocked(lists: &Lists, items: &[TodoItem], index: usize) -> bool {
+    !items[index].done && !open_prerequisites(lists, items, index).is_empty()
+}
+
+/// Whether making `task` depend on `prerequisite` would close a loop, i.e.
+/// `prerequisite` already (indirectly) depends on `task`.
+pub fn creates_cycle(lists: &Lists, task: &TaskRef, prerequisite: &TaskRef) -> bool {
+    let mut seen = HashSet::new();
+    let mut stack = vec![prerequisite.clone()];
+    while let Some(current) = stack.pop() {
+        if &current == task {
+            return true;
+        }
+        if !seen.insert(current.clone()) {
+            continue;
+        }
+        if let Some(item) = find(lists, &current) {
+            stack.extend(item.depends.iter().cloned());
+        }
+    }
+    false
+}
+
+/// Points dependencies on tasks in list `from` at list `to`, either for
+/// the tasks in `ids` or, when `ids` is `None`, for every task. Returns
+/// whether anything changed.
+pub fn retarget(items: &mut [TodoItem], from: &str, to: &str, ids: Option<&[String]>) -> bool {
+    let mut changed = false;
+    for dep in items.iter_mut().flat_map(|item| item.depends.iter_mut()) {
+        if dep.list == from && ids.is_none_or(|ids| ids.contains(&dep.id)) {
+            dep.list = to.to_string();
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// Turns `<list>:<num>` (a task address) into `<list>:<id>`; references that
+/// already use an ID are returned unchanged if the task exists.
+pub fn resolve(lists: &Lists, reference: &str) -> Option<TaskRef> {
+    let task = TaskRef::parse(reference)?;
+    let items = lists.get(&task.list)?;
+    if item::is_address(&task.id) {
+        let index = item::resolve(items, &task.id)?;
+        return Some(TaskRef { list: task.list, id: items[index].id.clone() });
+    }
+    find(lists, &task).map(|_| task)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task(id: &str, depends: &[&str]) -> TodoItem {
+        let mut item = TodoItem::new(format!("task {}", id));
+        item.id = id.to_string();
+        item.depends = depends.iter().map(|dep| TaskRef::parse(dep).unwrap()).collect();
+        item
+    }
+
+    fn task_ref(s: &str) -> TaskRef {
+        TaskRef::parse(s).unwrap()
+    }
+
+    #[test]
+    fn parses_and_writes_references() {
+        assert_eq!(task_ref("work:ab12"), TaskRef { list: "work".to_string(), id: "ab12".to_string() });
+        assert_eq!(task_ref("a%3Ab:ab12").list, "a:b");
+        assert_eq!(task_ref("a%3Ab:ab12").to_string(), "a%3Ab:ab12");
+        assert_eq!(TaskRef::parse("work:"), None);
+        assert_eq!(TaskRef::parse("ab12"), None);
+    }
+
+    #[test]
+    fn a_task_depending_on_itself_is_a_cycle() {
+        let lists = Lists::from([("work".to_string(), vec![task("a", &[])])]);
+        assert!(creates_cycle(&lists, &task_ref("work:a"), &task_ref("work:a")));
+    }
+
+    #[test]
+    fn finds_cycles_through_other_tasks_and_lists() {
+        let lists = Lists::from([
+            ("work".to_string(), vec![task("a", &[]), task("b", &["home:c"])]),
+            ("home".to_string(), vec![task("c", &["work:a"])]),
+        ]);
+        // b -> c -> a, so a can't depend on b, directly or through c
+        assert!(creates_cycle(&lists, &task_ref("work:a"), &task_ref("work:b")));
+        assert!(creates_cycle(&lists, &task_ref("work:a"), &task_ref("home:c")));
+        assert!(!creates_cycle(&lists, &task_ref("work:b"), &task_ref("work:a")));
+        // A loop elsewhere doesn't hang the search
+        let lists = Lists::from([("work".to_string(), vec![task("a", &["work:b"]), task("b", &["work:a"])])]);
+        assert!(!creates_cycle(&lists, &task_ref("work:c"), &task_ref("work:a")));
+    }
+
+    #[test]
+    fn open_prerequisites_in_other_lists_block() {
+        let mut lists = Lists::from([
+            ("work".to_string(), vec![task("a", &["home:c"]), task("b", &[])]),
+            ("home".to_string(), vec![task("c", &[])]),
+        ]);
+        lists.get_mut("work").unwrap()[1].depth = 1;
+        let items = lists["work"].clone();
+        assert!(is_blocked(&lists, &items, 0));
+        // Subtasks wait for their parents' prerequisites too
+        assert_eq!(open_prerequisites(&lists, &items, 1), [task_ref("home:c")]);
+
+        lists.get_mut("home").unwrap()[0].done = true;
+        assert!(!is_blocked(&lists, &items, 0));
+        assert!(!is_blocked(&lists, &items, 1));
+    }
+
+    #[test]
+    fn done_and_missing_prerequisites_do_not_block() {
+        let mut done = task("b", &[]);
+        done.done = true;
+        let lists = Lists::from([("work".to_string(), vec![task("a", &["work:b", "work:gone"]), done])]);
+        assert!(!is_blocked(&lists, &lists["work"], 0));
+    }
+
+    #[test]
+    fn resolves_addresses_to_ids() {
+        let mut items = vec![task("a", &[]), task("b", &[])];
+        items[1].depth = 1;
+        let lists = Lists::from([("home".to_string(), items)]);
+        assert_eq!(resolve(&lists, "home:1.1"), Some(task_ref("home:b")));
+        assert_eq!(resolve(&lists, "home:a"), Some(task_ref("home:a")));
+        assert_eq!(resolve(&lists, "home:2"), None);
+        assert_eq!(resolve(&lists, "away:1"), None);
+    }
+}
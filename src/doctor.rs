@@ -0,0 +1,436 @@
+// doctor.rs
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use chrono::{Local, NaiveDate};
+
+use crate::backup::BACKUP_DIR;
+use crate::caldav::CALDAV_STATE_DIR;
+use crate::comments;
+use crate::config::CONFIG_FILE;
+use crate::deps::TaskRef;
+use crate::item::{self, TodoItem};
+use crate::merge::TOMBSTONE_FILE;
+use crate::sync::GIT_FILES;
+use crate::{names, TodoApp, ARCHIVE_DIR};
+
+/// Where `todo doctor --fix` keeps the originals of what it changes.
+pub const DOCTOR_BACKUP_DIR: &str = ".doctor-backups";
+
+/// A problem found in one list. `line` is 1-based.
+struct Problem {
+    line: usize,
+    message: String,
+}
+
+/// A list as read from disk, together with what is wrong with it.
+struct Checked {
+    name: String,
+    /// Parsed tasks, already repaired, with the line each came from.
+    items: Vec<(usize, TodoItem)>,
+    problems: Vec<Problem>,
+}
+
+/// Strips what the old parser left behind in task text: it cut two
+/// characters short when removing ` [[ NO TAGS ]]`, leaving ` [` behind on
+/// every save, and didn't recognise `[[ TAGS : ... ]]` at all.
+fn clean_text(text: &str) -> String {
+    let mut text = text.replace("[[ NO TAGS ]]", "");
+    loop {
+        let trimmed = text.trim_end();
+        match trimmed.strip_suffix('[') {
+            Some(rest) => text = rest.to_string(),
+            None => {
+                text = trimmed.to_string();
+                break;
+            }
+        }
+    }
+    text
+}
+
+impl TodoApp {
+    /// Entries in `base_dir` that aren't lists or something todo keeps there
+    /// itself, e.g. editor swap files, `.DS_Store` or stray folders.
+    fn stray_files(&self) -> io::Result<Vec<String>> {
+        let mut stray = Vec::new();
+        for entry in fs::read_dir(&self.base_dir)?.flatten() {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            let is_dir = entry.file_type().is_ok_and(|t| t.is_dir());
+            let known = if GIT_FILES.contains(&file_name.as_str())
+                || file_name == TOMBSTONE_FILE
+                || (self.project && file_name == CONFIG_FILE)
+            {
+                true
+            } else if is_dir {
+                [ARCHIVE_DIR, BACKUP_DIR, CALDAV_STATE_DIR, DOCTOR_BACKUP_DIR].contains(&file_name.as_str())
+            } else {
+                names::list_name(&file_name).is_some()
+            };
+            if !known {
+                stray.push(if is_dir { format!("{}/", file_name) } else { file_name });
+            }
+        }
+        stray.sort();
+        Ok(stray)
+    }
+
+    /// The name a list file from an older version gets, which is its own
+    /// list name unless a list by that name has turned up since.
+    fn legacy_target(&self, list_name: &str) -> String {
+        (1..)
+            .map(|n| if n == 1 { list_name.to_string() } else { format!("{} ({})", list_name, n) })
+            .find(|name| !self.get_list_path(name).exists())
+            .unwrap()
+    }
+
+    /// Reads a list byte by byte, noting anything `read_items` would have
+    /// to paper over, without writing anything back.
+    fn check_list(&self, name: &str) -> io::Result<Checked> {
+        let bytes = fs::read(self.get_list_path(name))?;
+        let mut problems = Vec::new();
+        let mut items = Vec::new();
+
+        let mut lines: Vec<&[u8]> = bytes.split(|&b| b == b'\n').collect();
+        if lines.last().is_some_and(|last| last.is_empty()) {
+            lines.pop();
+        }
+
+        for (i, raw) in lines.into_iter().enumerate() {
+            let line_number = i + 1;
+            let mut line = match std::str::from_utf8(raw) {
+                Ok(line) => line.to_string(),
+                Err(_) => {
+                    problems.push(Problem { line: line_number, message: "invalid UTF-8".to_string() });
+                    String::from_utf8_lossy(raw).into_owned()
+                }
+            };
+            if let Some(stripped) = line.strip_suffix('\r') {
+                problems.push(Problem { line: line_number, message: "Windows line ending".to_string() });
+                line = stripped.to_string();
+            }
+            if line.trim().is_empty() {
+                problems.push(Problem { line: line_number, message: "blank line".to_string() });
+                continue;
+            }
+
+            let mut item = TodoItem::from_string(&line);
+            let cleaned = clean_text(&item.text);
+            if cleaned != item.text {
+                problems.push(Problem {
+                    line: line_number,
+                    message: format!("corrupted text '{}' (left over by an older version)", item.text.trim()),
+                });
+                item.text = cleaned;
+            } else if line.matches("[[ NO TAGS ]]").count() > 1
+                || line.matches("[[ TAGS : ").count() > 1
+                || (line.contains("[[ NO TAGS ]]") && line.contains("[[ TAGS : "))
+            {
+                problems.push(Problem { line: line_number, message: "duplicated tag suffixes".to_string() });
+            }
+            items.push((line_number, item));
+        }
+
+        // Same as item::normalize: no task more than one level below the last
+        let mut max_depth = 0;
+        for (_, item) in items.iter_mut() {
+            item.depth = item.depth.min(max_depth);
+            max_depth = item.depth + 1;
+        }
+
+        Ok(Checked { name: name.to_string(), items, problems })
+    }
+
+    /// Checks that need every list at once or the filesystem: duplicate IDs,
+    /// dependencies on missing tasks, dead tags and duplicate tasks.
+    fn check_contents(lists: &mut [Checked]) {
+        let mut all_ids: HashSet<TaskRef> = HashSet::new();
+        for list in lists.iter() {
+            for (_, item) in &list.items {
+                all_ids.insert(TaskRef { list: list.name.clone(), id: item.id.clone() });
+            }
+        }
+
+        let mut renamed: HashMap<TaskRef, TaskRef> = HashMap::new();
+        for list in lists.iter_mut() {
+            // IDs are only referred to as `<list>:<id>`, so they need only
+            // be unique within their list
+            let mut seen_ids: HashSet<String> = HashSet::new();
+            let mut seen_tasks: HashMap<(Option<usize>, String, Option<NaiveDate>, bool), usize> = HashMap::new();
+            let mut parents: Vec<usize> = Vec::new();
+            let mut effective_parents: Vec<Option<usize>> = Vec::new();
+            // Duplicates by index, with the index of the first copy
+            let mut merged_into: HashMap<usize, usize> = HashMap::new();
+
+            for index in 0..list.items.len() {
+                let (line, item) = &mut list.items[index];
+                let line = *line;
+
+                if !item.id.is_empty() && !seen_ids.insert(item.id.clone()) {
+                    list.problems.push(Problem { line, message: format!("duplicate task ID '{}'", item.id) });
+                    item.id = item::new_id();
+                }
+
+                let before = item.depends.len();
+                item.depends.retain(|dep| all_ids.contains(dep));
+                if item.depends.len() != before {
+                    list.problems.push(Problem { line, message: "depends on a task that no longer exists".to_string() });
+                }
+
+                // `<file>:<line>` tags from TODO comments are alive while their file is
+                let exists = |tag: &PathBuf| {
+                    tag.exists() || comments::parse_location(tag).is_some_and(|(file, _)| file.exists())
+                };
+                let dead: Vec<PathBuf> = item
+                    .tags
+                    .iter()
+                    .filter(|tag| item::is_path_tag(tag) && !exists(tag))
+                    .cloned()
+                    .collect();
+                for tag in &dead {
+                    list.problems.push(Problem { line, message: format!("tag points to a missing path: {}", tag.display()) });
+                }
+                item.tags.retain(|tag| !dead.contains(tag));
+
+                // Identical text under the same parent counts as a duplicate,
+                // unless the due dates differ as with recurring tasks.
+                // Subtasks of a duplicate count as under the copy it folds
+                // into, so they fold into that copy's subtasks in turn.
+                parents.truncate(item.depth);
+                let parent = parents.last().map(|p| *merged_into.get(p).unwrap_or(p));
+                parents.push(index);
+                effective_parents.push(parent);
+                let key = (parent, item.text.trim().to_lowercase(), item.due, item.done);
+                match seen_tasks.get(&key) {
+                    Some(&first) => {
+                        list.problems.push(Problem {
+                            line,
+                            message: format!("duplicate of the task on line {}", list.items[first].0),
+                        });
+                        merged_into.insert(index, first);
+                    }
+                    None => {
+                        seen_tasks.insert(key, index);
+                    }
+                }
+            }
+            if merged_into.is_empty() {
+                continue;
+            }
+
+            // Fold each duplicate into the first copy: its tags, note and
+            // dependencies join the copy's, and references to it move there
+            let mut folds: Vec<(usize, usize)> = merged_into.iter().map(|(&dup, &first)| (dup, first)).collect();
+            folds.sort_unstable();
+            for (duplicate, first) in folds {
+                let duplicate = list.items[duplicate].1.clone();
+                let first = &mut list.items[first].1;
+                for tag in duplicate.tags {
+                    first.add_tag(tag);
+                }
+                for dep in duplicate.depends {
+                    if !first.depends.contains(&dep) {
+                        first.depends.push(dep);
+                    }
+                }
+                first.note = match (first.note.take(), duplicate.note) {
+                    (Some(a), Some(b)) if a != b => Some(format!("{}\n\n{}", a, b)),
+                    (a, b) => a.or(b),
+                };
+                if !duplicate.id.is_empty() && duplicate.id != first.id {
+                    renamed.insert(
+                        TaskRef { list: list.name.clone(), id: duplicate.id },
+                        TaskRef { list: list.name.clone(), id: first.id.clone() },
+                    );
+                }
+            }
+
+            // Rebuild the tree without the duplicates, their subtasks
+            // following the first copy's own
+            let mut children: Vec<Vec<usize>> = vec![Vec::new(); list.items.len()];
+            let mut roots = Vec::new();
+            for (index, parent) in effective_parents.into_iter().enumerate() {
+                if merged_into.contains_key(&index) {
+                    continue;
+                }
+                match parent {
+                    Some(parent) => children[parent].push(index),
+                    None => roots.push(index),
+                }
+            }
+            let mut old: Vec<Option<(usize, TodoItem)>> = list.items.drain(..).map(Some).collect();
+            let mut stack: Vec<(usize, usize)> = roots.into_iter().rev().map(|index| (index, 0)).collect();
+            while let Some((index, depth)) = stack.pop() {
+                if let Some((line, mut item)) = old[index].take() {
+                    item.depth = depth;
+                    list.items.push((line, item));
+                }
+                stack.extend(children[index].iter().rev().map(|&child| (child, depth + 1)));
+            }
+        }
+
+        // Dependencies on a folded duplicate now point at its first copy
+        for list in lists.iter_mut() {
+            for (line, item) in list.items.iter_mut() {
+                if !item.depends.iter().any(|dep| renamed.contains_key(dep)) {
+                    continue;
+                }
+                let own = TaskRef { list: list.name.clone(), id: item.id.clone() };
+                let mut depends: Vec<TaskRef> = Vec::new();
+                for dep in &item.depends {
+                    let dep = renamed.get(dep).unwrap_or(dep);
+                    if *dep != own && !depends.contains(dep) {
+                        depends.push(dep.clone());
+                    }
+                }
+                item.depends = depends;
+                list.problems.push(Problem {
+                    line: *line,
+                    message: "depends on a duplicate task; now depends on its first copy".to_string(),
+                });
+            }
+        }
+    }
+
+    pub(crate) fn doctor(&self, fix: bool) -> io::Result<()> {
+        println!("Checking {}", self.base_dir.display());
+
+        let mut lists = Vec::new();
+        for name in self.list_names()? {
+            lists.push(self.check_list(&name)?);
+        }
+        Self::check_contents(&mut lists);
+        // List files named before names were encoded are lists all the same
+        let (legacy, stray): (Vec<String>, Vec<String>) = self
+            .stray_files()?
+            .into_iter()
+            .partition(|file_name| names::legacy_list_name(file_name).is_some());
+
+        let mut total = 0;
+        for list in &mut lists {
+            if list.problems.is_empty() {
+                continue;
+            }
+            list.problems.sort_by_key(|p| p.line);
+            println!("\n{} ({}):", list.name, names::file_name(&list.name));
+            for problem in &list.problems {
+                println!("  line {}: {}", problem.line, problem.message);
+            }
+            total += list.problems.len();
+        }
+        if !legacy.is_empty() {
+            println!("\nLists with file names from an older version (not shown):");
+            for file_name in &legacy {
+                let list_name = names::legacy_list_name(file_name).unwrap_or_default();
+                println!("  {} -> {}", file_name, names::file_name(&self.legacy_target(&list_name)));
+            }
+            total += legacy.len();
+        }
+        if !stray.is_empty() {
+            println!("\nFiles that are not todo lists (ignored):");
+            for name in &stray {
+                println!("  {}", name);
+            }
+            total += stray.len();
+        }
+
+        if total == 0 {
+            println!("No problems found.");
+            return Ok(());
+        }
+        if !fix {
+            println!("\n{} problem(s) found. Run 'todo doctor --fix' to repair them.", total);
+            return Ok(());
+        }
+
+        let backup_dir = self.base_dir
+            .join(DOCTOR_BACKUP_DIR)
+            .join(Local::now().format("%Y%m%d-%H%M%S").to_string());
+        fs::create_dir_all(&backup_dir)?;
+
+        let mut writes: Vec<(String, Vec<TodoItem>)> = Vec::new();
+        for list in lists.iter().filter(|list| !list.problems.is_empty()) {
+            let file_name = names::file_name(&list.name);
+            fs::copy(self.base_dir.join(&file_name), backup_dir.join(&file_name))?;
+            let mut items: Vec<TodoItem> = list.items.iter().map(|(_, item)| item.clone()).collect();
+            item::normalize(&mut items);
+            item::assign_ids(&mut items);
+            item::roll_up(&mut items);
+            writes.push((list.name.clone(), items));
+        }
+        let writes: Vec<(&str, &[TodoItem])> = writes
+            .iter()
+            .map(|(name, items)| (name.as_str(), items.as_slice()))
+            .collect();
+        self.write_lists(&writes)?;
+
+        for file_name in &legacy {
+            let list_name = names::legacy_list_name(file_name).unwrap_or_default();
+            let path = self.base_dir.join(file_name);
+            fs::copy(&path, backup_dir.join(file_name))?;
+            fs::rename(&path, self.get_list_path(&self.legacy_target(&list_name)))?;
+        }
+        for name in &stray {
+            let name = name.trim_end_matches('/');
+            fs::rename(self.base_dir.join(name), backup_dir.join(name))?;
+        }
+
+        println!("\nRepaired {} problem(s). Originals were saved in {}", total, backup_dir.display());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task(id: &str, text: &str, depth: usize) -> TodoItem {
+        let mut item = TodoItem::new(text.to_string());
+        item.id = id.to_string();
+        item.depth = depth;
+        item
+    }
+
+    fn checked(name: &str, items: Vec<TodoItem>) -> Checked {
+        Checked {
+            name: name.to_string(),
+            items: items.into_iter().enumerate().map(|(i, item)| (i + 1, item)).collect(),
+            problems: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn folds_duplicates_with_their_subtasks_into_the_first_copy() {
+        let mut copy = task("p2", "plan", 0);
+        copy.note = Some("from the copy".to_string());
+        copy.depends = vec![TaskRef { list: "home".to_string(), id: "h1".to_string() }];
+        let mut errand = task("h1", "errand", 0);
+        errand.depends = vec![TaskRef { list: "work".to_string(), id: "p2".to_string() }];
+        let mut lists = vec![
+            checked("work", vec![
+                task("p1", "plan", 0),
+                task("c1", "draft", 1),
+                copy,
+                task("c2", "review", 1),
+                task("c3", "draft", 1),
+                task("c4", "sources", 2),
+            ]),
+            checked("home", vec![errand]),
+        ];
+
+        TodoApp::check_contents(&mut lists);
+
+        let work: Vec<(String, usize)> = lists[0].items.iter().map(|(_, item)| (item.id.clone(), item.depth)).collect();
+        let expected = [("p1", 0), ("c1", 1), ("c4", 2), ("c2", 1)];
+        assert_eq!(work, expected.map(|(id, depth)| (id.to_string(), depth)));
+        let plan = &lists[0].items[0].1;
+        assert_eq!(plan.note.as_deref(), Some("from the copy"));
+        assert_eq!(plan.depends, [TaskRef { list: "home".to_string(), id: "h1".to_string() }]);
+        // The other list's dependency follows the fold, and that list gets rewritten
+        assert_eq!(lists[1].items[0].1.depends, [TaskRef { list: "work".to_string(), id: "p1".to_string() }]);
+        assert!(!lists[1].problems.is_empty());
+    }
+}
@@ -0,0 +1,214 @@
+// hook.rs
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::deps::TaskRef;
+use crate::item::{self, TodoItem};
+use crate::{names, TodoApp};
+
+/// The phrase that closes a task from a commit message.
+const CLOSES: &str = "closes todo:";
+/// Tasks closed by a commit are tagged `commit:<hash>`.
+const COMMIT_PREFIX: &str = "commit:";
+/// Marks hooks we wrote, so reinstalling replaces them but never anyone
+/// else's.
+const HOOK_MARKER: &str = "# Installed by 'todo hook install'";
+/// The hooks we install, and the arguments each passes on to us.
+const HOOKS: &[(&str, &str)] = &[("commit-msg", "hook commit-msg \"$1\""), ("post-commit", "hook post-commit")];
+
+/// The tasks a commit message closes with `closes todo:<list>#<id>`. List
+/// names with spaces are written encoded, as in dependencies.
+pub fn closed_tasks(message: &str) -> Vec<TaskRef> {
+    // ASCII lowercasing keeps byte offsets the same
+    let lower = message.to_ascii_lowercase();
+    lower
+        .match_indices(CLOSES)
+        .filter(|(start, _)| !lower[..*start].ends_with(|c: char| c.is_alphanumeric()))
+        .filter_map(|(start, _)| {
+            let rest = &message[start + CLOSES.len()..];
+            let reference = rest.split(char::is_whitespace).next()?;
+            let reference = reference.trim_end_matches(['.', ',', ';', ':', ')', ']']);
+            let (list, id) = reference.split_once('#')?;
+            if list.is_empty() || id.is_empty() {
+                return None;
+            }
+            Some(TaskRef { list: names::normalize(&names::decode(list)), id: id.to_string() })
+        })
+        .collect()
+}
+
+/// Whether `tag` is the `commit:<hash>` of a commit that closed its task.
+pub fn is_commit_tag(tag: &Path) -> bool {
+    tag.to_str().is_some_and(|tag| tag.starts_with(COMMIT_PREFIX))
+}
+
+/// The index of a referenced task, by ID or else by its number.
+fn find_task(items: &[TodoItem], id: &str) -> Option<usize> {
+    items.iter().position(|item| item.id == id).or_else(|| item::resolve(items, id))
+}
+
+/// Runs git in the current repository, which is what hooks run in.
+fn git(args: &[&str]) -> io::Result<String> {
+    let output = Command::new("git").args(args).output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim_end().to_string())
+}
+
+impl TodoApp {
+    /// Adds our hooks to the repository in the current directory.
+    pub(crate) fn install_hooks(&self) -> io::Result<()> {
+        let hooks_dir = match git(&["rev-parse", "--git-path", "hooks"]) {
+            Ok(dir) => env::current_dir()?.join(dir),
+            Err(_) => {
+                println!("Error: Not inside a git repository");
+                return Ok(());
+            }
+        };
+        fs::create_dir_all(&hooks_dir)?;
+        let exe = env::current_exe()?;
+
+        for (name, args) in HOOKS {
+            let path = hooks_dir.join(name);
+            if path.exists() && !fs::read_to_string(&path).unwrap_or_default().contains(HOOK_MARKER) {
+                println!("Skipped {}: {} is someone else's hook", name, path.display());
+                continue;
+            }
+            // Commits carry on as usual if the binary goes away
+            let script = format!(
+                "#!/bin/sh\n{}\n[ -x \"{exe}\" ] || exit 0\nexec \"{exe}\" {}\n",
+                HOOK_MARKER,
+                args,
+                exe = exe.display()
+            );
+            fs::write(&path, script)?;
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                fs::set_permissions(&path, fs::Permissions::from_mode(0o755))?;
+            }
+            println!("Installed {} hook: {}", name, path.display());
+        }
+        println!("Commit messages containing 'closes todo:<list>#<id>' now mark that task done.");
+        Ok(())
+    }
+
+    /// Checks that every task a commit message closes exists, so a typo
+    /// stops the commit instead of being silently ignored.
+    pub(crate) fn check_commit_message(&self, message_file: &str) -> io::Result<bool> {
+        let message = fs::read_to_string(message_file)?;
+        // Lines git strips from the message don't count
+        let message: String = message.lines().filter(|line| !line.starts_with('#')).collect::<Vec<_>>().join("\n");
+        let mut ok = true;
+        for task in closed_tasks(&message) {
+            let found = self.get_list_path(&task.list).exists()
+                && find_task(&self.read_items(&task.list)?, &task.id).is_some();
+            if !found {
+                println!("Error: No task '{}' in list '{}' to close", task.id, task.list);
+                ok = false;
+            }
+        }
+        Ok(ok)
+    }
+
+    /// Marks the tasks the last commit closes done, tagged with its hash.
+    pub(crate) fn close_from_commit(&self) -> io::Result<()> {
+        let log = git(&["log", "-1", "--format=%h%n%B"])?;
+        let (hash, message) = log.split_once('\n').unwrap_or((log.as_str(), ""));
+        let tag = PathBuf::from(format!("{}{}", COMMIT_PREFIX, hash));
+
+        let mut by_list: BTreeMap<String, Vec<TaskRef>> = BTreeMap::new();
+        for task in closed_tasks(message) {
+            by_list.entry(task.list.clone()).or_default().push(task);
+        }
+        for (list_name, tasks) in by_list {
+            if !self.get_list_path(&list_name).exists() {
+                println!("Warning: List '{}' not found.", list_name);
+                continue;
+            }
+            let mut items = self.read_items(&list_name)?;
+            let before = items.clone();
+            let mut closed = Vec::new();
+            for task in &tasks {
+                let index = match find_task(&items, &task.id) {
+                    Some(index) => index,
+                    None => {
+                        println!("Warning: No task '{}' in list '{}' to close", task.id, list_name);
+                        continue;
+                    }
+                };
+                let end = item::subtree_end(&items, index);
+                for item in &mut items[index..end] {
+                    item.done = true;
+                }
+                items[index].add_tag(tag.clone());
+                closed.push(items[index].text.clone());
+            }
+            if closed.is_empty() {
+                continue;
+            }
+            item::roll_up(&mut items);
+            item::spawn_recurrences(&before, &mut items);
+            self.write_items(&list_name, &items)?;
+            for text in closed {
+                println!("todo: '{}' marked done in list '{}' by commit {}", text, list_name, hash);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn task_ref(list: &str, id: &str) -> TaskRef {
+        TaskRef { list: list.to_string(), id: id.to_string() }
+    }
+
+    #[test]
+    fn finds_closed_tasks_in_commit_messages() {
+        assert_eq!(closed_tasks("Fix parser\n\nCloses todo:work#ab12."), [task_ref("work", "ab12")]);
+        assert_eq!(
+            closed_tasks("closes todo:work#ab12, closes todo:home#3 and CLOSES TODO:side%20project#cd34"),
+            [task_ref("work", "ab12"), task_ref("home", "3"), task_ref("side project", "cd34")]
+        );
+        // Only the phrase on its own counts, with both parts present
+        assert!(closed_tasks("encloses todo:work#ab12").is_empty());
+        assert!(closed_tasks("closes todo:work# closes todo:#ab12 closes todo:work").is_empty());
+        assert!(closed_tasks("Refactor, nothing closed").is_empty());
+    }
+
+    #[test]
+    fn commit_messages_naming_unknown_tasks_are_refused() {
+        let base_dir = env::temp_dir().join(format!("todo-test-hook-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&base_dir);
+        fs::create_dir_all(&base_dir).unwrap();
+        let config = Config::at(base_dir.join("config")).unwrap();
+        let app = TodoApp { base_dir: base_dir.clone(), config, project: false };
+        let mut item = TodoItem::new("write tests".to_string());
+        item.id = "ab12".to_string();
+        app.write_items("work", &[item]).unwrap();
+
+        let message = base_dir.join("COMMIT_EDITMSG");
+        let check = |text: &str| {
+            fs::write(&message, text).unwrap();
+            app.check_commit_message(message.to_str().unwrap()).unwrap()
+        };
+        assert!(check("Add tests\n\ncloses todo:work#ab12"));
+        assert!(check("Add tests, closes todo:work#1"));
+        assert!(check("Add tests\n# closes todo:work#zz99 (a comment git strips)"));
+        assert!(!check("Add tests, closes todo:work#zz99"));
+        assert!(!check("Add tests, closes todo:work#2"));
+        assert!(!check("Add tests, closes todo:elsewhere#ab12"));
+
+        fs::remove_dir_all(base_dir).unwrap();
+    }
+}
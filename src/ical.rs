@@ -0,0 +1,456 @@
+// ical.rs
+
+use std::io;
+use std::path::PathBuf;
+
+use chrono::{Local, NaiveDate, NaiveDateTime, TimeZone, Utc, Weekday};
+
+use crate::item::{self, Priority, TodoItem};
+use crate::recur::{self, Recurrence};
+use crate::{names, TodoApp};
+
+const PRODID: &str = "-//todo//todo lists//EN";
+
+/// A task read back from a VTODO, with the UIDs it was stored under.
+pub struct Vtodo {
+    pub uid: String,
+    pub item: TodoItem,
+    pub parent: Option<String>,
+    /// `LAST-MODIFIED`, in local time.
+    pub modified: Option<NaiveDateTime>,
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') | Some('N') => out.push('\n'),
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+/// Splits a property's value on commas that aren't escaped.
+fn split_list(value: &str) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut escaped = false;
+    for c in value.chars() {
+        match c {
+            ',' if !escaped => parts.push(std::mem::take(&mut current)),
+            _ => current.push(c),
+        }
+        escaped = c == '\\' && !escaped;
+    }
+    parts.push(current);
+    parts.iter().map(|part| unescape(part)).filter(|part| !part.is_empty()).collect()
+}
+
+/// Folds a content line at 75 bytes as RFC 5545 requires, and ends it.
+fn fold(line: &str) -> String {
+    let mut out = String::new();
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > 75 {
+            out.push_str("\r\n ");
+            width = 1;
+        }
+        out.push(c);
+        width += c.len_utf8();
+    }
+    out.push_str("\r\n");
+    out
+}
+
+fn utc(time: NaiveDateTime) -> String {
+    let time = Local.from_local_datetime(&time).earliest().map(|t| t.with_timezone(&Utc).naive_utc()).unwrap_or(time);
+    time.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+fn weekday_code(weekday: Weekday) -> &'static str {
+    match weekday {
+        Weekday::Mon => "MO",
+        Weekday::Tue => "TU",
+        Weekday::Wed => "WE",
+        Weekday::Thu => "TH",
+        Weekday::Fri => "FR",
+        Weekday::Sat => "SA",
+        Weekday::Sun => "SU",
+    }
+}
+
+fn rrule(recur: Recurrence) -> String {
+    match recur {
+        Recurrence::Days(n) => format!("FREQ=DAILY;INTERVAL={}", n),
+        Recurrence::Weeks(n) => format!("FREQ=WEEKLY;INTERVAL={}", n),
+        Recurrence::Months(n) => format!("FREQ=MONTHLY;INTERVAL={}", n),
+        Recurrence::On(weekday) => format!("FREQ=WEEKLY;BYDAY={}", weekday_code(weekday)),
+        Recurrence::MonthlyOn(day) => format!("FREQ=MONTHLY;BYMONTHDAY={}", day),
+    }
+}
+
+/// The rules `rrule` writes; anything more elaborate is ignored.
+fn parse_rrule(value: &str) -> Option<Recurrence> {
+    let mut freq = None;
+    let mut interval = 1;
+    let mut by_day = None;
+    let mut by_month_day = None;
+    for part in value.split(';') {
+        match part.split_once('=')? {
+            ("FREQ", f) => freq = Some(f),
+            ("INTERVAL", n) => interval = n.parse().ok().filter(|n| (1..=recur::MAX_INTERVAL).contains(n))?,
+            ("BYDAY", day) => {
+                by_day = [Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri, Weekday::Sat, Weekday::Sun]
+                    .into_iter()
+                    .find(|&w| weekday_code(w) == day)
+            }
+            ("BYMONTHDAY", day) => by_month_day = day.parse().ok().filter(|d| (1..=31).contains(d)),
+            _ => {}
+        }
+    }
+    match (freq?, by_day, by_month_day) {
+        ("DAILY", None, None) => Some(Recurrence::Days(interval)),
+        ("WEEKLY", Some(weekday), None) if interval == 1 => Some(Recurrence::On(weekday)),
+        ("WEEKLY", None, None) => Some(Recurrence::Weeks(interval)),
+        ("MONTHLY", None, Some(day)) if interval == 1 => Some(Recurrence::MonthlyOn(day)),
+        ("MONTHLY", None, None) => Some(Recurrence::Months(interval)),
+        _ => None,
+    }
+}
+
+/// The UID a task is exported under. Task IDs are only unique within their
+/// list, so the list is part of it.
+pub fn uid(list_name: &str, id: &str) -> String {
+    format!("{}-{}@todo", names::url_segment(list_name), id)
+}
+
+/// Wraps components in a VCALENDAR, named `name` in calendar apps.
+pub fn calendar(name: Option<&str>, components: &[String]) -> String {
+    let mut out = String::from("BEGIN:VCALENDAR\r\nVERSION:2.0\r\n");
+    out.push_str(&fold(&format!("PRODID:{}", PRODID)));
+    if let Some(name) = name {
+        out.push_str(&fold(&format!("X-WR-CALNAME:{}", escape(name))));
+    }
+    for component in components {
+        out.push_str(component);
+    }
+    out.push_str("END:VCALENDAR\r\n");
+    out
+}
+
+/// A task as a VTODO. Tags become categories, the note the description,
+/// and a subtask points at its parent with `RELATED-TO`.
+pub fn vtodo(item: &TodoItem, uid: &str, parent: Option<&str>) -> String {
+    let mut lines = vec![
+        "BEGIN:VTODO".to_string(),
+        format!("UID:{}", uid),
+        format!("DTSTAMP:{}", utc(Local::now().naive_local())),
+        format!("SUMMARY:{}", escape(&item.text)),
+        format!("STATUS:{}", if item.done { "COMPLETED" } else { "NEEDS-ACTION" }),
+    ];
+    if let Some(created) = item.created {
+        lines.push(format!("CREATED:{}", utc(created.and_hms_opt(0, 0, 0).unwrap_or_default())));
+    }
+    if let Some(updated) = item.updated {
+        lines.push(format!("LAST-MODIFIED:{}", utc(updated)));
+    }
+    if let Some(due) = item.due {
+        lines.push(format!("DUE;VALUE=DATE:{}", due.format("%Y%m%d")));
+        // A repeating VTODO needs a start to count from
+        if let Some(recur) = item.recur {
+            lines.push(format!("DTSTART;VALUE=DATE:{}", due.format("%Y%m%d")));
+            lines.push(format!("RRULE:{}", rrule(recur)));
+        }
+    }
+    if let Some(priority) = item.priority {
+        let value = match priority {
+            Priority::High => 1,
+            Priority::Medium => 5,
+            Priority::Low => 9,
+        };
+        lines.push(format!("PRIORITY:{}", value));
+    }
+    if !item.tags.is_empty() {
+        let categories: Vec<String> = item.tags.iter().map(|tag| escape(&tag.to_string_lossy())).collect();
+        lines.push(format!("CATEGORIES:{}", categories.join(",")));
+    }
+    if let Some(note) = &item.note {
+        lines.push(format!("DESCRIPTION:{}", escape(note)));
+    }
+    if let Some(parent) = parent {
+        lines.push(format!("RELATED-TO;RELTYPE=PARENT:{}", parent));
+    }
+    lines.push("END:VTODO".to_string());
+    lines.iter().map(|line| fold(line)).collect()
+}
+
+/// An all-day VEVENT on a task's due date, with a reminder that morning,
+/// for calendar apps that don't show tasks.
+pub fn due_event(item: &TodoItem, uid: &str) -> Option<String> {
+    let due = item.due?;
+    let mut lines = vec![
+        "BEGIN:VEVENT".to_string(),
+        format!("UID:{}-due", uid),
+        format!("DTSTAMP:{}", utc(Local::now().naive_local())),
+        format!("SUMMARY:{}", escape(&format!("Due: {}", item.text))),
+        format!("DTSTART;VALUE=DATE:{}", due.format("%Y%m%d")),
+        format!("DTEND;VALUE=DATE:{}", due.succ_opt()?.format("%Y%m%d")),
+        "TRANSP:TRANSPARENT".to_string(),
+    ];
+    if let Some(note) = &item.note {
+        lines.push(format!("DESCRIPTION:{}", escape(note)));
+    }
+    lines.extend([
+        "BEGIN:VALARM".to_string(),
+        "ACTION:DISPLAY".to_string(),
+        format!("DESCRIPTION:{}", escape(&item.text)),
+        "TRIGGER:PT9H".to_string(),
+        "END:VALARM".to_string(),
+        "END:VEVENT".to_string(),
+    ]);
+    Some(lines.iter().map(|line| fold(line)).collect())
+}
+
+fn parse_date(value: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(value.get(..8)?, "%Y%m%d").ok()
+}
+
+fn parse_time(value: &str) -> Option<NaiveDateTime> {
+    let time = NaiveDateTime::parse_from_str(value.trim_end_matches('Z'), "%Y%m%dT%H%M%S").ok()?;
+    if value.ends_with('Z') {
+        Some(Utc.from_utc_datetime(&time).with_timezone(&Local).naive_local())
+    } else {
+        Some(time)
+    }
+}
+
+/// Splits a content line into its name, parameters and value.
+fn split_property(line: &str) -> Option<(String, String, &str)> {
+    let mut quoted = false;
+    let colon = line.char_indices().find_map(|(i, c)| match c {
+        '"' => {
+            quoted = !quoted;
+            None
+        }
+        ':' if !quoted => Some(i),
+        _ => None,
+    })?;
+    let (head, value) = (&line[..colon], &line[colon + 1..]);
+    let (name, params) = head.split_once(';').unwrap_or((head, ""));
+    Some((name.to_uppercase(), params.to_uppercase(), value))
+}
+
+/// Reads every VTODO in an iCalendar file.
+pub fn parse_vtodos(ics: &str) -> Vec<Vtodo> {
+    // Unfold continuation lines first
+    let mut lines: Vec<String> = Vec::new();
+    for line in ics.lines() {
+        match line.strip_prefix([' ', '\t']) {
+            Some(rest) if !lines.is_empty() => lines.last_mut().unwrap().push_str(rest),
+            _ => lines.push(line.to_string()),
+        }
+    }
+
+    let mut todos = Vec::new();
+    let mut current: Option<Vtodo> = None;
+    // Nested components such as VALARM have properties of their own
+    let mut nested = 0;
+    for line in &lines {
+        let (name, params, value) = match split_property(line) {
+            Some(property) => property,
+            None => continue,
+        };
+        match (name.as_str(), value.to_uppercase().as_str()) {
+            ("BEGIN", "VTODO") => {
+                current = Some(Vtodo { uid: String::new(), item: TodoItem::default(), parent: None, modified: None });
+                continue;
+            }
+            ("END", "VTODO") => {
+                if let Some(todo) = current.take().filter(|todo| !todo.uid.is_empty()) {
+                    todos.push(todo);
+                }
+                continue;
+            }
+            ("BEGIN", _) if current.is_some() => nested += 1,
+            ("END", _) if current.is_some() => nested -= 1,
+            _ => {}
+        }
+        let todo = match current.as_mut() {
+            Some(todo) if nested == 0 => todo,
+            _ => continue,
+        };
+        let item = &mut todo.item;
+        match name.as_str() {
+            "UID" => todo.uid = value.trim().to_string(),
+            "SUMMARY" => item.text = unescape(value),
+            "DESCRIPTION" => item.note = Some(unescape(value)).filter(|note| !note.is_empty()),
+            "STATUS" => item.done = value.eq_ignore_ascii_case("COMPLETED"),
+            "DUE" => item.due = parse_date(value),
+            "CREATED" => item.created = parse_date(value),
+            "LAST-MODIFIED" => todo.modified = parse_time(value),
+            "RRULE" => item.recur = parse_rrule(&value.to_uppercase()),
+            "PRIORITY" => {
+                item.priority = match value.trim().parse::<u8>() {
+                    Ok(1..=4) => Some(Priority::High),
+                    Ok(5) => Some(Priority::Medium),
+                    Ok(6..=9) => Some(Priority::Low),
+                    _ => None,
+                }
+            }
+            "CATEGORIES" => item.tags.extend(split_list(value).into_iter().map(PathBuf::from)),
+            "RELATED-TO" if params.is_empty() || params.contains("RELTYPE=PARENT") => {
+                todo.parent = Some(value.trim().to_string())
+            }
+            _ => {}
+        }
+    }
+    todos
+}
+
+impl TodoApp {
+    /// Prints one list, or every list for `all`, as an iCalendar file with
+    /// a VTODO per task, and with `events` a VEVENT per open task that has
+    /// a due date.
+    pub(crate) fn export_ical(&self, list_name: &str, events: bool) -> io::Result<()> {
+        let lists = if list_name == "all" {
+            self.get_available_lists()?
+        } else if self.get_list_path(list_name).exists() {
+            vec![list_name.to_string()]
+        } else {
+            println!("List '{}' not found.", list_name);
+            return Ok(());
+        };
+
+        let mut components = Vec::new();
+        for list_name in &lists {
+            let items = self.read_items(list_name)?;
+            let parents = item::parents(&items);
+            for (index, item) in items.iter().enumerate() {
+                let parent = parents[index].map(|p| uid(list_name, &items[p].id));
+                let uid = uid(list_name, &item.id);
+                components.push(vtodo(item, &uid, parent.as_deref()));
+                if events && !item.done {
+                    components.extend(due_event(item, &uid));
+                }
+            }
+        }
+
+        let name = if list_name == "all" { "todo" } else { list_name };
+        print!("{}", calendar(Some(name), &components));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task(line: &str) -> TodoItem {
+        TodoItem::from_string(line)
+    }
+
+    #[test]
+    fn uid_includes_the_list() {
+        assert_eq!(uid("work", "abc123"), "work-abc123@todo");
+        assert_eq!(uid("home/bills, 2024", "x1"), "home%2Fbills%2C%202024-x1@todo");
+        assert_ne!(uid("a", "x1"), uid("b", "x1"));
+    }
+
+    #[test]
+    fn escapes_text_values() {
+        let text = "a, b; c\\d\nnext";
+        assert_eq!(escape(text), r"a\, b\; c\\d\nnext");
+        assert_eq!(unescape(&escape(text)), text);
+        assert_eq!(split_list("a\\,b,c,,d"), ["a,b", "c", "d"]);
+    }
+
+    #[test]
+    fn folds_long_lines() {
+        let line = format!("SUMMARY:{}", "é".repeat(60));
+        let folded = fold(&line);
+        assert!(folded.split("\r\n").all(|part| part.len() <= 75));
+        assert_eq!(folded.replace("\r\n ", "").trim_end(), line);
+        assert_eq!(fold("UID:x"), "UID:x\r\n");
+    }
+
+    #[test]
+    fn round_trips_a_task() {
+        let mut item = task("water, then feed; the cat [[ DONE ]] [[ PRIORITY : high ]] [[ DUE : 2024-05-06 ]] [[ REPEAT : every 2 weeks ]] [[ CREATED : 2024-05-01 ]] [[ NOTE : line one\\nline two ]] [[ TAGS : /home/me | errands ]]");
+        item.id = "c4t".to_string();
+        let ics = calendar(Some("home"), &[vtodo(&item, "home-c4t@todo", Some("home-p4r@todo"))]);
+        assert!(ics.starts_with("BEGIN:VCALENDAR\r\n") && ics.ends_with("END:VCALENDAR\r\n"));
+        assert!(ics.contains("X-WR-CALNAME:home\r\n"));
+
+        let todos = parse_vtodos(&ics);
+        assert_eq!(todos.len(), 1);
+        let todo = &todos[0];
+        assert_eq!(todo.uid, "home-c4t@todo");
+        assert_eq!(todo.parent.as_deref(), Some("home-p4r@todo"));
+        assert_eq!(todo.item.text, item.text);
+        assert!(todo.item.done);
+        assert_eq!(todo.item.priority, Some(Priority::High));
+        assert_eq!(todo.item.due, item.due);
+        assert_eq!(todo.item.recur, Some(Recurrence::Weeks(2)));
+        assert_eq!(todo.item.created, item.created);
+        assert_eq!(todo.item.note.as_deref(), Some("line one\nline two"));
+        assert_eq!(todo.item.tags, item.tags);
+    }
+
+    #[test]
+    fn round_trips_recurrence_rules() {
+        for recur in [
+            Recurrence::Days(1),
+            Recurrence::Days(3),
+            Recurrence::Weeks(2),
+            Recurrence::Months(6),
+            Recurrence::On(Weekday::Thu),
+            Recurrence::MonthlyOn(15),
+        ] {
+            assert_eq!(parse_rrule(&rrule(recur)), Some(recur));
+        }
+        assert_eq!(parse_rrule("FREQ=YEARLY"), None);
+        assert_eq!(parse_rrule("FREQ=DAILY;INTERVAL=0"), None);
+        assert_eq!(parse_rrule("FREQ=DAILY;INTERVAL=100000"), None);
+    }
+
+    #[test]
+    fn parses_what_other_apps_write() {
+        let ics = "BEGIN:VCALENDAR\r\n\
+                   BEGIN:VTODO\r\n\
+                   UID:remote-1\r\n\
+                   SUMMARY:a long summary that was\r\n  folded\r\n\
+                   STATUS:NEEDS-ACTION\r\n\
+                   DUE;TZID=Europe/Berlin:20240506T120000\r\n\
+                   PRIORITY:7\r\n\
+                   BEGIN:VALARM\r\n\
+                   DESCRIPTION:not the task's\r\n\
+                   END:VALARM\r\n\
+                   END:VTODO\r\n\
+                   BEGIN:VTODO\r\n\
+                   SUMMARY:no uid, skipped\r\n\
+                   END:VTODO\r\n\
+                   END:VCALENDAR\r\n";
+        let todos = parse_vtodos(ics);
+        assert_eq!(todos.len(), 1);
+        let item = &todos[0].item;
+        assert_eq!(item.text, "a long summary that was folded");
+        assert!(!item.done);
+        assert_eq!(item.due, NaiveDate::from_ymd_opt(2024, 5, 6));
+        assert_eq!(item.priority, Some(Priority::Low));
+        assert_eq!(item.note, None);
+    }
+}
@@ -0,0 +1,803 @@
+// item.rs
+
+use std::collections::hash_map::{DefaultHasher, RandomState};
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use chrono::{Datelike, Local, NaiveDate, NaiveDateTime, Timelike};
+
+use crate::deps::TaskRef;
+use crate::recur::{self, Recurrence};
+use crate::{hook, repo};
+
+/// A single task. On disk every task is one line: the task text followed by
+/// `[[ ... ]]` blocks holding its other fields, e.g.
+///
+/// `write docs [[ NOTE : see\nthe wiki ]] [[ TAGS : /home/me/proj ]]`
+///
+/// Subtasks follow their parent and are indented two spaces per level.
+#[derive(Clone, Debug, Default)]
+pub struct TodoItem {
+    /// Stable identifier that survives reordering, e.g. for `<list>:<id>`.
+    pub id: String,
+    pub text: String,
+    pub tags: Vec<PathBuf>,
+    pub note: Option<String>,
+    pub depth: usize,
+    pub done: bool,
+    pub due: Option<NaiveDate>,
+    pub recur: Option<Recurrence>,
+    pub depends: Vec<TaskRef>,
+    pub priority: Option<Priority>,
+    pub created: Option<NaiveDate>,
+    /// When the task last changed, so `todo merge` can tell which of two
+    /// copies is newer.
+    pub updated: Option<NaiveDateTime>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    High,
+    Medium,
+    Low,
+}
+
+impl Priority {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "high" | "h" | "1" => Some(Priority::High),
+            "medium" | "med" | "m" | "2" => Some(Priority::Medium),
+            "low" | "l" | "3" => Some(Priority::Low),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Priority::High => write!(f, "high"),
+            Priority::Medium => write!(f, "medium"),
+            Priority::Low => write!(f, "low"),
+        }
+    }
+}
+
+/// Orders in which a list can be kept.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SortKey {
+    Priority,
+    Due,
+    Created,
+}
+
+impl SortKey {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "priority" => Some(SortKey::Priority),
+            "due" => Some(SortKey::Due),
+            "created" => Some(SortKey::Created),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for SortKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SortKey::Priority => write!(f, "priority"),
+            SortKey::Due => write!(f, "due"),
+            SortKey::Created => write!(f, "created"),
+        }
+    }
+}
+
+impl TodoItem {
+    pub fn new(text: String) -> Self {
+        TodoItem {
+            id: new_id(),
+            text,
+            created: Some(recur::today()),
+            updated: Some(now()),
+            ..Default::default()
+        }
+    }
+
+    pub fn from_string(s: &str) -> Self {
+        let mut item = TodoItem::default();
+        let mut rest = s.trim_start_matches(' ');
+        item.depth = (s.len() - rest.len()) / 2;
+
+        // Peel `[[ ... ]]` blocks off the end of the line until we reach one
+        // we don't recognise; whatever is left is the task text.
+        while let Some((head, block)) = split_last_block(rest) {
+            if !item.apply_block(block) {
+                break;
+            }
+            rest = head;
+        }
+
+        item.text = rest.to_string();
+        item
+    }
+
+    fn apply_block(&mut self, block: &str) -> bool {
+        match block {
+            "NO TAGS" => return true,
+            "DONE" => {
+                self.done = true;
+                return true;
+            }
+            _ => {}
+        }
+
+        let (key, value) = match block.split_once(':') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => return false,
+        };
+        match key {
+            "TAGS" => {
+                let tags = value
+                    .split('|')
+                    .map(|t| PathBuf::from(t.trim()))
+                    .filter(|t| !t.as_os_str().is_empty());
+                // Blocks are read back to front
+                let mut tags: Vec<PathBuf> = tags.collect();
+                tags.append(&mut self.tags);
+                self.tags = tags;
+            }
+            "ID" => self.id = value.to_string(),
+            "DEPENDS" => {
+                let mut depends: Vec<TaskRef> = value.split('|').filter_map(TaskRef::parse).collect();
+                depends.append(&mut self.depends);
+                self.depends = depends;
+            }
+            "NOTE" => self.note = Some(unescape(value)),
+            "DUE" => match NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+                Ok(date) => self.due = Some(date),
+                Err(_) => return false,
+            },
+            "PRIORITY" => match Priority::parse(value) {
+                Some(priority) => self.priority = Some(priority),
+                None => return false,
+            },
+            "CREATED" => match NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+                Ok(date) => self.created = Some(date),
+                Err(_) => return false,
+            },
+            "UPDATED" => match NaiveDateTime::parse_from_str(value, UPDATED_FORMAT) {
+                Ok(time) => self.updated = Some(time),
+                Err(_) => return false,
+            },
+            "REPEAT" => match Recurrence::parse(value) {
+                Some(recur) => self.recur = Some(recur),
+                None => return false,
+            },
+            _ => return false,
+        }
+        true
+    }
+
+    pub fn add_tag(&mut self, new_tag: PathBuf) {
+        self.tags.retain(|tag| tag != &new_tag);
+        self.tags.push(new_tag);
+    }
+
+    /// A one-line rendering for task listings.
+    pub fn summary(&self) -> String {
+        let mut result = String::new();
+        if self.done {
+            result.push_str("[x] ");
+        }
+        result.push_str(&self.text);
+        let mut details = Vec::new();
+        if let Some(priority) = self.priority {
+            details.push(format!("{} priority", priority));
+        }
+        if let Some(due) = self.due {
+            details.push(format!("due {}", due));
+        }
+        if let Some(recur) = self.recur {
+            details.push(recur.to_string());
+        }
+        if !details.is_empty() {
+            result.push_str(&format!(" ({})", details.join(", ")));
+        }
+        if self.note.is_some() {
+            result.push_str(" (+note)");
+        }
+        result.push_str(&self.tags_block());
+        result
+    }
+
+    fn tags_block(&self) -> String {
+        if self.tags.is_empty() {
+            return " [[ NO TAGS ]]".to_string();
+        }
+        format!(" [[ TAGS : {} ]]", self.tags
+            .iter()
+            .map(|p| p.to_string_lossy().to_string())
+            .collect::<Vec<_>>()
+            .join(" | "))
+    }
+}
+
+impl fmt::Display for TodoItem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", "  ".repeat(self.depth), self.text)?;
+        if !self.id.is_empty() {
+            write!(f, " [[ ID : {} ]]", self.id)?;
+        }
+        if self.done {
+            write!(f, " [[ DONE ]]")?;
+        }
+        if let Some(priority) = self.priority {
+            write!(f, " [[ PRIORITY : {} ]]", priority)?;
+        }
+        if let Some(due) = self.due {
+            write!(f, " [[ DUE : {} ]]", due)?;
+        }
+        if let Some(recur) = self.recur {
+            write!(f, " [[ REPEAT : {} ]]", recur)?;
+        }
+        if !self.depends.is_empty() {
+            write!(f, " [[ DEPENDS : {} ]]", self.depends
+                .iter()
+                .map(|d| d.to_string())
+                .collect::<Vec<_>>()
+                .join(" | "))?;
+        }
+        if let Some(created) = self.created {
+            write!(f, " [[ CREATED : {} ]]", created)?;
+        }
+        if let Some(updated) = self.updated {
+            write!(f, " [[ UPDATED : {} ]]", updated.format(UPDATED_FORMAT))?;
+        }
+        if let Some(note) = &self.note {
+            write!(f, " [[ NOTE : {} ]]", escape(note))?;
+        }
+        write!(f, "{}", self.tags_block())
+    }
+}
+
+const UPDATED_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// The current local time, to the second as stored in `[[ UPDATED ]]`.
+pub fn now() -> NaiveDateTime {
+    let now = Local::now().naive_local();
+    now.with_nanosecond(0).unwrap_or(now)
+}
+
+/// Whether two versions of a task differ in anything but their position
+/// and `updated` time.
+fn changed(before: &TodoItem, after: &TodoItem) -> bool {
+    let strip = |item: &TodoItem| TodoItem { depth: 0, updated: None, ..item.clone() }.to_string();
+    strip(before) != strip(after)
+}
+
+/// Sets `updated` on every task in `items` that is new or different
+/// compared to `before`, the same list as last written.
+pub fn stamp_changes(before: &[TodoItem], items: &mut [TodoItem]) {
+    let now = now();
+    for item in items.iter_mut() {
+        match before.iter().find(|old| old.id == item.id) {
+            Some(old) if !changed(old, item) => {}
+            _ => item.updated = Some(now),
+        }
+    }
+}
+
+/// A short random ID: a letter followed by five letters or digits.
+pub fn new_id() -> String {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0));
+    id_from_hash(hasher.finish())
+}
+
+fn id_from_hash(mut n: u64) -> String {
+    const ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyz0123456789";
+    let mut id = String::with_capacity(6);
+    id.push(ALPHABET[(n % 26) as usize] as char);
+    n /= 26;
+    for _ in 0..5 {
+        id.push(ALPHABET[(n % 36) as usize] as char);
+        n /= 36;
+    }
+    id
+}
+
+/// Gives every task without an ID a fresh one. Returns whether any changed.
+pub fn assign_ids(items: &mut [TodoItem]) -> bool {
+    let mut changed = false;
+    for item in items.iter_mut().filter(|item| item.id.is_empty()) {
+        item.id = new_id();
+        changed = true;
+    }
+    changed
+}
+
+/// Gives every task without an ID one made from its text and how many tasks
+/// before it share that text, instead of a random one. Copies of a list
+/// being merged then agree on the IDs of tasks from before IDs existed.
+pub fn assign_text_ids(items: &mut [TodoItem]) {
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    for item in items.iter_mut().filter(|item| item.id.is_empty()) {
+        let text = item.text.trim().to_string();
+        let occurrence = seen.entry(text.clone()).or_default();
+        *occurrence += 1;
+        let mut hasher = DefaultHasher::new();
+        (text, *occurrence).hash(&mut hasher);
+        item.id = id_from_hash(hasher.finish());
+    }
+}
+
+/// Dotted addresses (`3`, `3.2`, ...) for each task, in file order.
+pub fn addresses(items: &[TodoItem]) -> Vec<String> {
+    let mut counters: Vec<usize> = Vec::new();
+    items
+        .iter()
+        .map(|item| {
+            // Clamp so a child without a parent is treated as one level up
+            let depth = item.depth.min(counters.len());
+            counters.truncate(depth + 1);
+            if counters.len() == depth {
+                counters.push(0);
+            }
+            counters[depth] += 1;
+            counters
+                .iter()
+                .map(|n| n.to_string())
+                .collect::<Vec<_>>()
+                .join(".")
+        })
+        .collect()
+}
+
+/// Pulls in any task indented more than one level below the task before it,
+/// so every subtask has a parent.
+pub fn normalize(items: &mut [TodoItem]) {
+    let mut max_depth = 0;
+    for item in items.iter_mut() {
+        item.depth = item.depth.min(max_depth);
+        max_depth = item.depth + 1;
+    }
+}
+
+/// Checks that `address` looks like `3` or `3.2.1`.
+pub fn is_address(address: &str) -> bool {
+    address
+        .split('.')
+        .all(|part| part.parse::<usize>().map(|n| n > 0).unwrap_or(false))
+}
+
+/// Picks tasks out of `items` by a selection such as `1,3,7`, `2-5`,
+/// `3.1-3.4`, `done`, `open`, `all` or `tag:~/proj` (tasks tagged with that
+/// path or anything beneath it). Parts are separated by commas. Returns the
+/// selected indexes in file order, or a message naming the bad part.
+pub fn select(items: &[TodoItem], selection: &str) -> Result<Vec<usize>, String> {
+    let mut indexes = Vec::new();
+    let parts: Vec<&str> = selection.split(',').map(str::trim).filter(|p| !p.is_empty()).collect();
+    if parts.is_empty() {
+        return Err("empty selection".to_string());
+    }
+
+    for part in parts {
+        match part {
+            "all" => indexes.extend(0..items.len()),
+            "done" => indexes.extend((0..items.len()).filter(|&i| items[i].done)),
+            "open" => indexes.extend((0..items.len()).filter(|&i| !items[i].done)),
+            _ if part.starts_with("tag:") => {
+                let path = expand_path(&part[4..]);
+                indexes.extend((0..items.len()).filter(|&i| {
+                    items[i].tags.iter().any(|tag| tag.starts_with(&path))
+                }));
+            }
+            _ => {
+                for address in expand_range(part, items.len())? {
+                    let index = resolve(items, &address)
+                        .ok_or_else(|| format!("no task {}", address))?;
+                    indexes.push(index);
+                }
+            }
+        }
+    }
+
+    indexes.sort_unstable();
+    indexes.dedup();
+    Ok(indexes)
+}
+
+/// Expands `2-5` or `3.1-3.4` (also written `3.1-4`) into addresses; a
+/// single address expands to itself. No list of `len` tasks has more
+/// siblings than that, so ranges ending past it are refused up front.
+fn expand_range(part: &str, len: usize) -> Result<Vec<String>, String> {
+    let invalid = || format!("invalid task '{}'", part);
+    let (first, last) = match part.split_once('-') {
+        Some(range) => range,
+        None if is_address(part) => return Ok(vec![part.to_string()]),
+        None => return Err(invalid()),
+    };
+    if !is_address(first) {
+        return Err(invalid());
+    }
+    let (prefix, start) = match first.rsplit_once('.') {
+        Some((prefix, start)) => (format!("{}.", prefix), start),
+        None => (String::new(), first),
+    };
+    let last = last.strip_prefix(prefix.as_str()).unwrap_or(last);
+    let start: usize = start.parse().map_err(|_| invalid())?;
+    let end: usize = last.parse().map_err(|_| invalid())?;
+    if end < start {
+        return Err(invalid());
+    }
+    if end > len {
+        return Err(format!("no task {}{}", prefix, end));
+    }
+    Ok((start..=end).map(|n| format!("{}{}", prefix, n)).collect())
+}
+
+/// Whether a tag names a file or directory, as most do, rather than
+/// something else recorded as a tag: the commit that closed the task or the
+/// branch it is worked on in.
+pub fn is_path_tag(tag: &Path) -> bool {
+    !hook::is_commit_tag(tag) && repo::parse_branch(tag).is_none()
+}
+
+/// Expands a leading `~` and makes relative paths absolute.
+pub fn expand_path(path: &str) -> PathBuf {
+    let path = match path.strip_prefix('~') {
+        Some(rest) => match dirs::home_dir() {
+            Some(home) => home.join(rest.trim_start_matches('/')),
+            None => PathBuf::from(path),
+        },
+        None => PathBuf::from(path),
+    };
+    if path.is_relative() {
+        if let Ok(current_dir) = std::env::current_dir() {
+            return current_dir.join(path);
+        }
+    }
+    path
+}
+
+/// Turns a sorted list of selected indexes into `(start, end)` ranges that
+/// each cover one selected task and its subtasks. Selected subtasks of an
+/// already selected task are covered by their parent.
+pub fn selected_subtrees(items: &[TodoItem], indexes: &[usize]) -> Vec<(usize, usize)> {
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for &index in indexes {
+        if ranges.last().is_some_and(|&(_, end)| index < end) {
+            continue;
+        }
+        ranges.push((index, subtree_end(items, index)));
+    }
+    ranges
+}
+
+/// Finds the index of the task with the given dotted address.
+pub fn resolve(items: &[TodoItem], address: &str) -> Option<usize> {
+    if !is_address(address) {
+        return None;
+    }
+    addresses(items).iter().position(|a| a == address)
+}
+
+/// The index of each task's parent, if it has one.
+pub fn parents(items: &[TodoItem]) -> Vec<Option<usize>> {
+    let mut stack: Vec<usize> = Vec::new();
+    items
+        .iter()
+        .enumerate()
+        .map(|(index, item)| {
+            stack.truncate(item.depth);
+            let parent = stack.last().copied();
+            stack.push(index);
+            parent
+        })
+        .collect()
+}
+
+/// The index just past the last descendant of `index`.
+pub fn subtree_end(items: &[TodoItem], index: usize) -> usize {
+    let depth = items[index].depth;
+    items[index + 1..]
+        .iter()
+        .position(|item| item.depth <= depth)
+        .map(|offset| index + 1 + offset)
+        .unwrap_or(items.len())
+}
+
+/// Makes the next occurrence of every recurring task in `before` that is
+/// done in `items` but wasn't before, together with fresh copies of its
+/// subtasks. Each occurrence is appended to the end of its parent (or of the
+/// list, for top-level tasks).
+pub fn spawn_recurrences(before: &[TodoItem], items: &mut Vec<TodoItem>) -> Vec<usize> {
+    let completed: Vec<usize> = (0..items.len())
+        .filter(|&i| items[i].done && items[i].recur.is_some() && !before[i].done)
+        .collect();
+
+    let mut spawned = Vec::new();
+    // Work backwards so earlier indexes stay valid as we insert
+    for &index in completed.iter().rev() {
+        let end = subtree_end(items, index);
+        let mut copies: Vec<TodoItem> = items[index..end].to_vec();
+        for copy in &mut copies {
+            copy.id = new_id();
+            copy.done = false;
+        }
+        let recur = copies[0].recur.unwrap();
+        // A due date too far out to move on keeps where it is
+        copies[0].due = recur.next_due(copies[0].due).or(copies[0].due);
+
+        let depth = items[index].depth;
+        let position = match (0..index).rev().find(|&i| items[i].depth < depth) {
+            Some(parent) => subtree_end(items, parent),
+            None => items.len(),
+        };
+        let count = copies.len();
+        items.splice(position..position, copies);
+        for i in spawned.iter_mut() {
+            if *i >= position {
+                *i += count;
+            }
+        }
+        spawned.push(position);
+    }
+    spawned
+}
+
+/// Splits `items` into runs of top-level tasks plus their subtasks.
+fn top_level_subtrees(items: Vec<TodoItem>) -> Vec<Vec<TodoItem>> {
+    let mut groups: Vec<Vec<TodoItem>> = Vec::new();
+    let root_depth = items.first().map(|item| item.depth).unwrap_or(0);
+    for item in items {
+        match groups.last_mut() {
+            Some(group) if item.depth > root_depth => group.push(item),
+            _ => groups.push(vec![item]),
+        }
+    }
+    groups
+}
+
+/// Sorts every group of sibling tasks by `key`, keeping subtasks with their
+/// parents. Tasks without a value for `key` go last; ties keep their order.
+pub fn sort_tree(items: &mut Vec<TodoItem>, key: SortKey) {
+    let mut groups = top_level_subtrees(std::mem::take(items));
+    for group in &mut groups {
+        let mut children = group.split_off(1);
+        sort_tree(&mut children, key);
+        group.append(&mut children);
+    }
+    groups.sort_by_key(|group| {
+        let item = &group[0];
+        match key {
+            SortKey::Priority => (item.priority.is_none(), item.priority.map(|p| p as i64).unwrap_or(0)),
+            SortKey::Due => (item.due.is_none(), item.due.map(|d| d.num_days_from_ce() as i64).unwrap_or(0)),
+            SortKey::Created => (item.created.is_none(), item.created.map(|d| d.num_days_from_ce() as i64).unwrap_or(0)),
+        }
+    });
+    *items = groups.concat();
+}
+
+/// Moves the task at `index` (with its subtasks) so it becomes sibling
+/// number `position` under the same parent. Returns its new index.
+pub fn move_to_position(items: &mut Vec<TodoItem>, index: usize, position: usize) -> usize {
+    let end = subtree_end(items, index);
+    let subtree: Vec<TodoItem> = items.drain(index..end).collect();
+    let depth = subtree[0].depth;
+
+    // The range of the parent's subtasks, now without the moved task
+    let parent = (0..index).rev().find(|&i| items[i].depth < depth);
+    let (first, last) = match parent {
+        Some(parent) => (parent + 1, subtree_end(items, parent)),
+        None => (0, items.len()),
+    };
+    let siblings: Vec<usize> = (first..last).filter(|&i| items[i].depth == depth).collect();
+
+    let target = match siblings.get(position.saturating_sub(1)) {
+        Some(&sibling) => sibling,
+        None => last,
+    };
+    items.splice(target..target, subtree);
+    target
+}
+
+/// Marks every task that has children as done exactly when all of its
+/// children are done, working from the deepest tasks upwards.
+pub fn roll_up(items: &mut [TodoItem]) {
+    for index in (0..items.len()).rev() {
+        let end = subtree_end(items, index);
+        if end == index + 1 {
+            continue;
+        }
+        let depth = items[index].depth;
+        items[index].done = items[index + 1..end]
+            .iter()
+            .filter(|item| item.depth == depth + 1)
+            .all(|item| item.done);
+    }
+}
+
+/// Splits `line` into everything before its trailing ` [[ ... ]]` block and
+/// the block's contents. Accepts the legacy `[[TAGS:...]]` spelling too.
+fn split_last_block(line: &str) -> Option<(&str, &str)> {
+    let body = line.strip_suffix("]]")?;
+    let start = body.rfind(" [[")?;
+    Some((&line[..start], body[start + 3..].trim()))
+}
+
+/// Escapes a free-form value so it fits on one line inside a block.
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => {}
+            '[' => out.push_str("\\["),
+            ']' => out.push_str("\\]"),
+            '|' => out.push_str("\\|"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some(c) => out.push(c),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tasks from lines in the list file format, where two spaces of
+    /// indent make a subtask.
+    fn items(lines: &[&str]) -> Vec<TodoItem> {
+        lines.iter().map(|line| TodoItem::from_string(line)).collect()
+    }
+
+    #[test]
+    fn expands_ranges() {
+        assert_eq!(expand_range("3", 5), Ok(vec!["3".to_string()]));
+        assert_eq!(expand_range("2-4", 5), Ok(vec!["2".to_string(), "3".to_string(), "4".to_string()]));
+        assert_eq!(expand_range("3.1-3.3", 5), Ok(vec!["3.1".to_string(), "3.2".to_string(), "3.3".to_string()]));
+        assert_eq!(expand_range("3.1-2", 5), Ok(vec!["3.1".to_string(), "3.2".to_string()]));
+        assert_eq!(expand_range("5-2", 5), Err("invalid task '5-2'".to_string()));
+        assert_eq!(expand_range("0-2", 5), Err("invalid task '0-2'".to_string()));
+        assert_eq!(expand_range("a-b", 5), Err("invalid task 'a-b'".to_string()));
+        assert_eq!(expand_range("done", 5), Err("invalid task 'done'".to_string()));
+    }
+
+    #[test]
+    fn refuses_ranges_longer_than_the_list() {
+        assert_eq!(expand_range("1-99999999999", 5), Err("no task 99999999999".to_string()));
+        assert_eq!(expand_range("2.1-2.6", 5), Err("no task 2.6".to_string()));
+        assert_eq!(expand_range("1-99999999999999999999999", 5), Err("invalid task '1-99999999999999999999999'".to_string()));
+    }
+
+    #[test]
+    fn selects_addresses_and_ranges() {
+        let items = items(&["a", "  a1", "  a2", "b", "c"]);
+        assert_eq!(select(&items, "1"), Ok(vec![0]));
+        assert_eq!(select(&items, "3,1.2"), Ok(vec![2, 4]));
+        assert_eq!(select(&items, "1.1-1.2, 2"), Ok(vec![1, 2, 3]));
+        assert_eq!(select(&items, "2-3,3"), Ok(vec![3, 4]));
+        assert_eq!(select(&items, "4"), Err("no task 4".to_string()));
+        assert_eq!(select(&items, "x"), Err("invalid task 'x'".to_string()));
+        assert_eq!(select(&items, " , "), Err("empty selection".to_string()));
+    }
+
+    #[test]
+    fn selects_by_status_and_tag() {
+        let mut items = items(&["a", "b", "c"]);
+        items[1].done = true;
+        items[2].add_tag(PathBuf::from("/work/proj/src"));
+        assert_eq!(select(&items, "all"), Ok(vec![0, 1, 2]));
+        assert_eq!(select(&items, "done"), Ok(vec![1]));
+        assert_eq!(select(&items, "open"), Ok(vec![0, 2]));
+        assert_eq!(select(&items, "tag:/work/proj"), Ok(vec![2]));
+        assert_eq!(select(&items, "tag:/work/pro"), Ok(vec![]));
+    }
+
+    #[test]
+    fn addresses_nested_tasks() {
+        let items = items(&["a", "  a1", "    a1x", "      a1x1", "    a1y", "  a2", "b", "  b1"]);
+        assert_eq!(addresses(&items), ["1", "1.1", "1.1.1", "1.1.1.1", "1.1.2", "1.2", "2", "2.1"]);
+        assert_eq!(resolve(&items, "1.1.1.1"), Some(3));
+        assert_eq!(resolve(&items, "1.1.2"), Some(4));
+        assert_eq!(resolve(&items, "2.1"), Some(7));
+        assert_eq!(resolve(&items, "1.1.3"), None);
+        assert_eq!(resolve(&items, "1..1"), None);
+        assert_eq!(parents(&items), [None, Some(0), Some(1), Some(2), Some(1), Some(0), None, Some(6)]);
+        assert_eq!(subtree_end(&items, 1), 5);
+        assert_eq!(subtree_end(&items, 6), 8);
+    }
+
+    #[test]
+    fn parents_are_done_when_all_their_subtasks_are() {
+        let mut items = items(&["a", "  a1", "    a1x", "    a1y", "  a2", "b"]);
+        items[2].done = true;
+        roll_up(&mut items);
+        assert!(!items[1].done && !items[0].done);
+
+        // Completion rolls up through every level
+        items[3].done = true;
+        items[4].done = true;
+        roll_up(&mut items);
+        assert!(items[1].done && items[0].done);
+        assert!(!items[5].done);
+
+        // Reopening a subtask reopens its parents
+        items[2].done = false;
+        roll_up(&mut items);
+        assert!(!items[1].done && !items[0].done);
+        assert!(items[4].done);
+    }
+
+    fn texts(items: &[TodoItem]) -> Vec<String> {
+        items.iter().map(|item| format!("{}{}", "  ".repeat(item.depth), item.text)).collect()
+    }
+
+    #[test]
+    fn sorts_siblings_and_keeps_subtasks_with_their_parents() {
+        let mut items = items(&["a", "  a1", "  a2", "    a2x", "b", "c", "  c1", "d"]);
+        for (index, priority) in [(0, Priority::Low), (2, Priority::High), (5, Priority::High), (7, Priority::Low)] {
+            items[index].priority = Some(priority);
+        }
+        sort_tree(&mut items, SortKey::Priority);
+        // Tasks without a priority go last, and equal ones keep their order
+        assert_eq!(texts(&items), ["c", "  c1", "a", "  a2", "    a2x", "  a1", "d", "b"]);
+
+        let mut items = self::items(&["a", "  a1", "b", "  b1", "  b2"]);
+        items[2].due = NaiveDate::from_ymd_opt(2024, 1, 1);
+        items[4].due = NaiveDate::from_ymd_opt(2024, 1, 1);
+        sort_tree(&mut items, SortKey::Due);
+        assert_eq!(texts(&items), ["b", "  b2", "  b1", "a", "  a1"]);
+    }
+
+    #[test]
+    fn moves_tasks_with_their_subtasks() {
+        let tree = ["a", "  a1", "b", "  b1", "  b2", "c"];
+
+        let mut items = items(&tree);
+        assert_eq!(move_to_position(&mut items, 2, 1), 0);
+        assert_eq!(texts(&items), ["b", "  b1", "  b2", "a", "  a1", "c"]);
+
+        // Position counts siblings without the moved task, so it lands there
+        let mut items = self::items(&tree);
+        assert_eq!(move_to_position(&mut items, 0, 2), 3);
+        assert_eq!(texts(&items), ["b", "  b1", "  b2", "a", "  a1", "c"]);
+
+        // Subtasks stay under their parent, and positions past the end append
+        let mut items = self::items(&tree);
+        assert_eq!(move_to_position(&mut items, 3, 9), 4);
+        assert_eq!(texts(&items), ["a", "  a1", "b", "  b2", "  b1", "c"]);
+    }
+
+    #[test]
+    fn selected_subtasks_are_covered_by_their_parent() {
+        let items = items(&["a", "  a1", "  a2", "b"]);
+        assert_eq!(selected_subtrees(&items, &[0, 2, 3]), vec![(0, 3), (3, 4)]);
+        assert_eq!(selected_subtrees(&items, &[1, 2]), vec![(1, 2), (2, 3)]);
+    }
+}
@@ -0,0 +1,297 @@
+// lsp.rs
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use serde_json::{json, Value};
+
+use crate::comments::{self, Comment};
+use crate::deps::Lists;
+use crate::item::{self, TodoItem};
+use crate::{names, TodoApp};
+
+/// The comments editors are offered to turn into tasks.
+const KEYWORDS: &[&str] = &["TODO", "FIXME"];
+/// The command behind the "add to todo list" code action.
+const PROMOTE_COMMAND: &str = "todo.promote";
+
+// Diagnostic severities and error codes from the protocol
+const INFORMATION: u8 = 3;
+const HINT: u8 = 4;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INTERNAL_ERROR: i64 = -32603;
+
+/// Reads one message, or `None` once the editor closes the connection.
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let length = length.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "message without Content-Length"))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn write_message(output: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    uri.strip_prefix("file://").map(|path| PathBuf::from(names::decode(path)))
+}
+
+/// The whole of a 1-based line, in the protocol's UTF-16 positions.
+fn line_range(lines: &[&str], line: usize) -> Value {
+    let index = line.saturating_sub(1).min(lines.len().saturating_sub(1));
+    let end = lines.get(index).map_or(0, |text| text.encode_utf16().count());
+    json!({
+        "start": { "line": index, "character": 0 },
+        "end": { "line": index, "character": end },
+    })
+}
+
+/// The line of `path` a tag points at: the tagged line for `<file>:<line>`
+/// tags, or the top for tags naming the file itself.
+fn tagged_line(tag: &Path, path: &Path) -> Option<usize> {
+    match comments::parse_location(tag) {
+        Some((file, line)) if file == path => Some(line),
+        _ if tag == path => Some(1),
+        _ => None,
+    }
+}
+
+/// Whether a task already covers `comment`: one tagged with its line, or
+/// with the same text somewhere in the file in case lines have moved.
+fn is_tracked(lists: &Lists, path: &Path, comment: &Comment) -> bool {
+    lists.values().flatten().any(|item| {
+        item.tags.iter().any(|tag| match comments::parse_location(tag) {
+            Some((file, line)) => file == path && (line == comment.line || item.text == comment.text),
+            None => false,
+        })
+    })
+}
+
+/// Tells the editor about an error without answering a request.
+fn log_error(output: &mut impl Write, message: &str) -> io::Result<()> {
+    write_message(output, &json!({
+        "jsonrpc": "2.0",
+        "method": "window/logMessage",
+        "params": { "type": 1, "message": message },
+    }))
+}
+
+impl TodoApp {
+    /// Talks the Language Server Protocol over stdin and stdout, so editors
+    /// can show the tasks tagged to a file and turn TODO comments into
+    /// tasks in `list_name`.
+    pub(crate) fn lsp(&self, list_name: &str) -> io::Result<()> {
+        let stdin = io::stdin();
+        let mut input = stdin.lock();
+        let mut output = io::stdout();
+        // The text of each open file, as the editor last sent it
+        let mut documents: HashMap<String, String> = HashMap::new();
+
+        while let Some(message) = read_message(&mut input)? {
+            let method = message["method"].as_str().unwrap_or("");
+            let params = &message["params"];
+            let uri = params["textDocument"]["uri"].as_str().unwrap_or("").to_string();
+
+            let result = match method {
+                "initialize" => Ok(Some(json!({
+                    "capabilities": {
+                        // Whole documents are sent on every change
+                        "textDocumentSync": 1,
+                        "codeActionProvider": true,
+                        "executeCommandProvider": { "commands": [PROMOTE_COMMAND] },
+                    },
+                    "serverInfo": { "name": "todo" },
+                }))),
+                "shutdown" => Ok(Some(Value::Null)),
+                "exit" => break,
+                "textDocument/didOpen" => {
+                    let text = params["textDocument"]["text"].as_str().unwrap_or("");
+                    documents.insert(uri.clone(), text.to_string());
+                    self.publish_diagnostics(&mut output, &uri, text).map(|_| None)
+                }
+                "textDocument/didChange" => {
+                    let changes = params["contentChanges"].as_array();
+                    if let Some(text) = changes.and_then(|c| c.last()).and_then(|c| c["text"].as_str()) {
+                        documents.insert(uri.clone(), text.to_string());
+                    }
+                    let text = documents.get(&uri).map(String::as_str).unwrap_or("");
+                    self.publish_diagnostics(&mut output, &uri, text).map(|_| None)
+                }
+                "textDocument/didSave" => {
+                    let text = documents.get(&uri).map(String::as_str).unwrap_or("");
+                    self.publish_diagnostics(&mut output, &uri, text).map(|_| None)
+                }
+                "textDocument/didClose" => {
+                    documents.remove(&uri);
+                    Ok(None)
+                }
+                "textDocument/codeAction" => {
+                    let text = documents.get(&uri).map(String::as_str).unwrap_or("");
+                    self.code_actions(list_name, &uri, text, &params["range"]).map(Some)
+                }
+                "workspace/executeCommand" if params["command"] == PROMOTE_COMMAND => {
+                    let arguments = &params["arguments"];
+                    match (arguments[0].as_str(), arguments[1].as_u64(), arguments[2].as_str()) {
+                        (Some(uri), Some(line), Some(text)) => {
+                            let result = self.promote(&mut output, list_name, uri, line as usize, text);
+                            if result.is_ok() {
+                                if let Some(document) = documents.get(uri) {
+                                    self.publish_diagnostics(&mut output, uri, document)?;
+                                }
+                            }
+                            result.map(|_| Some(Value::Null))
+                        }
+                        _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "expected [uri, line, text]")),
+                    }
+                }
+                _ => Ok(None),
+            };
+
+            // Only requests carry an ID, and each needs an answer
+            let id = match message.get("id") {
+                Some(id) => id.clone(),
+                None => {
+                    if let Err(e) = result {
+                        log_error(&mut output, &format!("{}: {}", method, e))?;
+                    }
+                    continue;
+                }
+            };
+            let response = match result {
+                Ok(Some(result)) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                Ok(None) => json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": { "code": METHOD_NOT_FOUND, "message": format!("unknown method '{}'", method) },
+                }),
+                Err(e) => json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": { "code": INTERNAL_ERROR, "message": e.to_string() },
+                }),
+            };
+            write_message(&mut output, &response)?;
+        }
+        Ok(())
+    }
+
+    /// Marks the open tasks tagged to a file, and the TODO comments in it
+    /// that aren't tasks yet.
+    fn publish_diagnostics(&self, output: &mut impl Write, uri: &str, text: &str) -> io::Result<()> {
+        let path = match uri_to_path(uri) {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let lists = self.read_all_lists()?;
+        let lines: Vec<&str> = text.lines().collect();
+        let mut diagnostics = Vec::new();
+
+        for (list_name, items) in &lists {
+            let addresses = item::addresses(items);
+            for (index, item) in items.iter().enumerate().filter(|(_, item)| !item.done) {
+                for line in item.tags.iter().filter_map(|tag| tagged_line(tag, &path)) {
+                    diagnostics.push(json!({
+                        "range": line_range(&lines, line),
+                        "severity": INFORMATION,
+                        "source": "todo",
+                        "message": format!("Task {} in list '{}': {}", addresses[index], list_name, item.text),
+                    }));
+                }
+            }
+        }
+        for comment in comments::find(text, KEYWORDS) {
+            if !is_tracked(&lists, &path, &comment) {
+                diagnostics.push(json!({
+                    "range": line_range(&lines, comment.line),
+                    "severity": HINT,
+                    "source": "todo",
+                    "message": format!("{} not in a todo list: {}", comment.keyword, comment.text),
+                }));
+            }
+        }
+
+        write_message(output, &json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "diagnostics": diagnostics },
+        }))
+    }
+
+    /// Offers to add each untracked TODO comment in `range` to the list.
+    fn code_actions(&self, list_name: &str, uri: &str, text: &str, range: &Value) -> io::Result<Value> {
+        let path = match uri_to_path(uri) {
+            Some(path) => path,
+            None => return Ok(json!([])),
+        };
+        let first = range["start"]["line"].as_u64().unwrap_or(0) as usize + 1;
+        let last = range["end"]["line"].as_u64().unwrap_or(0) as usize + 1;
+        let lists = self.read_all_lists()?;
+
+        let actions: Vec<Value> = comments::find(text, KEYWORDS)
+            .into_iter()
+            .filter(|comment| (first..=last).contains(&comment.line))
+            .filter(|comment| !is_tracked(&lists, &path, comment))
+            .map(|comment| {
+                let title = format!("Add to todo list '{}': {}", list_name, comment.text);
+                json!({
+                    "title": title,
+                    "kind": "quickfix",
+                    "command": {
+                        "title": title,
+                        "command": PROMOTE_COMMAND,
+                        "arguments": [uri, comment.line, comment.text],
+                    },
+                })
+            })
+            .collect();
+        Ok(json!(actions))
+    }
+
+    /// Adds a comment to the list as a task tagged with its `file:line`.
+    fn promote(&self, output: &mut impl Write, list_name: &str, uri: &str, line: usize, text: &str) -> io::Result<()> {
+        let path = uri_to_path(uri)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("not a file: {}", uri)))?;
+        let mut items = self.read_items(list_name)?;
+        let mut task = TodoItem::new(text.to_string());
+        task.add_tag(comments::location_tag(&path, line));
+        let id = task.id.clone();
+        items.push(task);
+        self.write_items(list_name, &items)?;
+        self.auto_commit(&[format!("lsp: add {}:{} to {}", path.display(), line, list_name)]);
+
+        // A saved sort order may have put it anywhere
+        let items = self.read_items(list_name)?;
+        let address = items
+            .iter()
+            .position(|item| item.id == id)
+            .map(|index| item::addresses(&items)[index].clone())
+            .unwrap_or_default();
+        write_message(output, &json!({
+            "jsonrpc": "2.0",
+            "method": "window/showMessage",
+            "params": { "type": 3, "message": format!("Task {} added to list '{}': {}", address, list_name, text) },
+        }))
+    }
+}
@@ -1,95 +1,286 @@
 // todo.rs
 
+mod backup;
+mod caldav;
+mod clipboard;
+mod comments;
+mod config;
+mod deps;
+mod doctor;
+mod hook;
+mod ical;
+mod item;
+mod lsp;
+mod merge;
+mod names;
+mod project;
+mod recur;
+mod repo;
+mod scan;
+mod serve;
+mod sync;
+
 use std::fs::{self, File, OpenOptions};
-use std::io::{self, BufRead, BufReader, Write, stdin, stdout};
-use std::path::PathBuf;
-use std::process::{Command, Stdio};
+use std::io::{self, Write, stdin, stdout};
+use std::path::{Path, PathBuf};
 use std::env;
+use std::process::Command;
+
+use config::Config;
+use deps::{Lists, TaskRef};
+use item::{Priority, SortKey, TodoItem};
+use recur::Recurrence;
 
 const TODO_DIR: &str = ".todo_lists";
+const ARCHIVE_DIR: &str = "archive";
 
 struct TodoApp {
     base_dir: PathBuf,
+    config: Config,
+    /// Whether `base_dir` is a project's `.todo` directory rather than the
+    /// one in home.
+    project: bool,
 }
 
-#[derive(Clone, Debug)]
-struct TodoItem {
-    text: String,
-    tags: Vec<PathBuf>,
-}
-
-impl TodoItem {
-    fn new(text: String) -> Self {
-        TodoItem {
-            text,
-            tags: Vec::new(),
+impl TodoApp {
+    /// Uses the lists of the project around the current directory if it has
+    /// a `.todo` directory, unless `global` asks for the ones in home. A
+    /// project keeps its list settings (`sort.*`) in `.todo/config`; all
+    /// others come from the user's own config.
+    fn new(global: bool) -> io::Result<Self> {
+        let home = dirs::home_dir().expect("Could not find home directory");
+        let project_dir = if global { None } else { project::find(&env::current_dir()?, &home) };
+        let project = project_dir.is_some();
+        let base_dir = project_dir.unwrap_or_else(|| home.join(TODO_DIR));
+        fs::create_dir_all(&base_dir)?;
+        let mut config = Config::load()?;
+        if project {
+            config = config.with_project(base_dir.join(config::CONFIG_FILE))?;
         }
+        let app = TodoApp { base_dir, config, project };
+        app.migrate_list_files()?;
+        Ok(app)
     }
 
-    fn to_string(&self) -> String {
-        let mut result = self.text.clone();
-        if !self.tags.is_empty() {
-            result.push_str(" [[ TAGS : ");
-            result.push_str(&self.tags
-                .iter()
-                .map(|p| p.to_string_lossy().to_string())
-                .collect::<Vec<_>>()
-                .join(" | "));
-            result.push_str(" ]]");
-        } else {
-            result.push_str(" [[ NO TAGS ]]");
+    /// Renames list files from before list names were encoded to their
+    /// encoded names, so they show up as lists again. A file whose new name
+    /// is already taken is left for `todo doctor` to report.
+    fn migrate_list_files(&self) -> io::Result<()> {
+        for dir in [self.base_dir.clone(), self.base_dir.join(ARCHIVE_DIR)] {
+            if !dir.is_dir() {
+                continue;
+            }
+            for entry in fs::read_dir(&dir)?.flatten() {
+                if !entry.file_type().is_ok_and(|t| t.is_file()) {
+                    continue;
+                }
+                let list_name = match entry.file_name().to_str().and_then(names::legacy_list_name) {
+                    Some(list_name) => list_name,
+                    None => continue,
+                };
+                let new_path = dir.join(names::file_name(&list_name));
+                if new_path.exists() {
+                    continue;
+                }
+                fs::rename(entry.path(), &new_path)?;
+                // stderr, as stdout may belong to `todo lsp`'s protocol
+                eprintln!("Renamed list file {} to {}", entry.path().display(), new_path.display());
+            }
         }
-        result
+        Ok(())
+    }
+
+    fn get_list_path(&self, list_name: &str) -> PathBuf {
+        // The name is encoded, so it can't point outside base_dir
+        self.base_dir.join(names::file_name(list_name))
     }
 
-    fn from_string(s: &str) -> Self {
-        if let Some(tag_start) = s.find(" [[TAGS:") {
-            if let Some(tag_end) = s.rfind("]]") {
-                let text = s[..tag_start].to_string();
-                let tags_str = &s[tag_start + 8..tag_end];
-                let tags = tags_str
-                    .split('|')
-                    .map(|t| PathBuf::from(t.trim()))
-                    .collect();
-                return TodoItem { text, tags };
+    /// Prints why `list_name` can't be used for a new list, if it can't.
+    fn check_new_list_name(list_name: &str) -> bool {
+        match names::validate(list_name) {
+            Ok(()) => true,
+            Err(e) => {
+                println!("Error: Invalid list name '{}': {}", list_name, e);
+                false
             }
         }
+    }
 
-        if s.ends_with(" [[ NO TAGS ]]") {
-            return TodoItem::new(s[..s.len() - 12].to_string());
+    fn add_task(&self, task: &str, list_name: &str, tags: &[PathBuf]) -> io::Result<()> {
+        if !Self::check_new_list_name(list_name) {
+            return Ok(());
         }
+        let file_path = self.get_list_path(list_name);
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(file_path)?;
 
-        TodoItem::new(s.to_string())
+        let mut item = TodoItem::new(task.to_string());
+        for tag in tags {
+            item.add_tag(tag.clone());
+        }
+        if self.saved_sort(list_name).is_some() {
+            // Rewrite the whole list so the new task lands in sorted order
+            drop(file);
+            let mut items = self.read_items(list_name)?;
+            items.push(item.clone());
+            self.write_items(list_name, &items)?;
+        } else {
+            writeln!(file, "{}", item)?;
+        }
+        println!("Task added to list '{}': {}", list_name, item.summary());
+        Ok(())
     }
 
-    fn add_tag(&mut self, new_tag: PathBuf) {
-        self.tags.retain(|tag| tag != &new_tag);
-        self.tags.push(new_tag);
+    fn add_subtask(&self, task: &str, parent: &str, list_name: &str, tags: &[PathBuf]) -> io::Result<()> {
+        if !self.get_list_path(list_name).exists() {
+            println!("List '{}' not found.", list_name);
+            return Ok(());
+        }
+
+        let mut items = self.read_items(list_name)?;
+        let index = match item::resolve(&items, parent) {
+            Some(index) => index,
+            None => {
+                println!("Error: Invalid task number");
+                return Ok(());
+            }
+        };
+
+        let mut subtask = TodoItem::new(task.to_string());
+        subtask.depth = items[index].depth + 1;
+        for tag in tags {
+            subtask.add_tag(tag.clone());
+        }
+        let position = item::subtree_end(&items, index);
+        items.insert(position, subtask);
+        item::roll_up(&mut items);
+        self.write_items(list_name, &items)?;
+
+        let address = &item::addresses(&items)[position];
+        println!("Subtask {} added to list '{}': {}", address, list_name, items[position].summary());
+        Ok(())
     }
-}
 
-impl TodoApp {
-    fn new() -> io::Result<Self> {
-        let home = dirs::home_dir().expect("Could not find home directory");
-        let base_dir = home.join(TODO_DIR);
-        fs::create_dir_all(&base_dir)?;
-        Ok(TodoApp { base_dir })
+    fn set_done(&self, selection: &str, list_name: &str, done: bool) -> io::Result<()> {
+        if !self.get_list_path(list_name).exists() {
+            println!("List '{}' not found.", list_name);
+            return Ok(());
+        }
+
+        let mut items = self.read_items(list_name)?;
+        let indexes = match item::select(&items, selection) {
+            Ok(indexes) => indexes,
+            Err(e) => {
+                println!("Error: {}", e);
+                return Ok(());
+            }
+        };
+
+        let before = items.clone();
+        for (start, end) in item::selected_subtrees(&items, &indexes) {
+            for item in &mut items[start..end] {
+                item.done = done;
+            }
+        }
+        item::roll_up(&mut items);
+        let spawned = item::spawn_recurrences(&before, &mut items);
+        self.write_items(list_name, &items)?;
+
+        let what = if indexes.len() == 1 {
+            format!("Task {}", selection)
+        } else {
+            format!("{} tasks", indexes.len())
+        };
+        if done {
+            println!("{} marked done in list '{}'", what, list_name);
+        } else {
+            println!("{} reopened in list '{}'", what, list_name);
+        }
+        let addresses = item::addresses(&items);
+        for position in spawned {
+            println!("Next occurrence added as task {}: {}", addresses[position], items[position].summary());
+        }
+        Ok(())
     }
 
-    fn get_list_path(&self, list_name: &str) -> PathBuf {
-        self.base_dir.join(format!("{}.txt", list_name))
+    fn set_due(&self, task: &str, list_name: &str, date: &str) -> io::Result<()> {
+        if !self.get_list_path(list_name).exists() {
+            println!("List '{}' not found.", list_name);
+            return Ok(());
+        }
+
+        let due = if date == "none" {
+            None
+        } else {
+            match recur::parse_date(date) {
+                Some(due) => Some(due),
+                None => {
+                    println!("Error: Invalid date '{}' (use YYYY-MM-DD, today, tomorrow or a weekday)", date);
+                    return Ok(());
+                }
+            }
+        };
+
+        let mut items = self.read_items(list_name)?;
+        let index = match item::resolve(&items, task) {
+            Some(index) => index,
+            None => {
+                println!("Error: Invalid task number");
+                return Ok(());
+            }
+        };
+
+        items[index].due = due;
+        self.write_items(list_name, &items)?;
+
+        match due {
+            Some(due) => println!("Task {} in list '{}' is due {}", task, list_name, due),
+            None => println!("Cleared due date of task {} in list '{}'", task, list_name),
+        }
+        Ok(())
     }
 
-    fn add_task(&self, task: &str, list_name: &str) -> io::Result<()> {
-        let file_path = self.get_list_path(list_name);
-        let mut file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(file_path)?;
+    fn set_recurrence(&self, task: &str, list_name: &str, rule: &str) -> io::Result<()> {
+        if !self.get_list_path(list_name).exists() {
+            println!("List '{}' not found.", list_name);
+            return Ok(());
+        }
+
+        let recur = if rule == "none" {
+            None
+        } else {
+            match Recurrence::parse(rule) {
+                Some(recur) => Some(recur),
+                None => {
+                    println!("Error: Invalid rule '{}' (e.g. every day, every monday, every 2 weeks, monthly on 1st)", rule);
+                    return Ok(());
+                }
+            }
+        };
 
-        let item = TodoItem::new(task.to_string());
-        writeln!(file, "{}", item.to_string())?;
-        println!("Task added to list '{}': {}", list_name, item.to_string());
+        let mut items = self.read_items(list_name)?;
+        let index = match item::resolve(&items, task) {
+            Some(index) => index,
+            None => {
+                println!("Error: Invalid task number");
+                return Ok(());
+            }
+        };
+
+        items[index].recur = recur;
+        if let Some(recur) = recur {
+            if items[index].due.is_none() {
+                items[index].due = Some(recur.first_due());
+            }
+        }
+        self.write_items(list_name, &items)?;
+
+        match recur {
+            Some(recur) => println!("Task {} in list '{}' now repeats {}", task, list_name, recur),
+            None => println!("Task {} in list '{}' no longer repeats", task, list_name),
+        }
         Ok(())
     }
 
@@ -97,14 +288,10 @@ impl TodoApp {
         match file_arg {
             Some(filename) => {
                 let current_dir = env::current_dir()?;
-                println!("Current directory: {}", current_dir.display());
-                println!("Filename argument: {}", filename);
-                
+
                 // If filename is given without path, prepend current directory
                 if !filename.contains('/') {
-                    let full_path = current_dir.join(filename.trim_matches('"'));  // Remove any quotes
-                    println!("Full resolved path: {}", full_path.display());
-                    Ok(full_path)
+                    Ok(current_dir.join(filename.trim_matches('"')))  // Remove any quotes
                 } else {
                     Ok(PathBuf::from(filename))
                 }
@@ -115,42 +302,43 @@ impl TodoApp {
         }
     }
 
-    fn add_tag(&self, file_arg: Option<&str>, task_num: usize, list_name: &str) -> io::Result<()> {
+    fn add_tag(&self, file_arg: Option<&str>, selection: &str, list_name: &str) -> io::Result<()> {
         let list_path = self.get_list_path(list_name);
         if !list_path.exists() {
             println!("List '{}' not found.", list_name);
             return Ok(());
         }
 
-        // Debug the incoming argument
-        println!("Received file argument: {:?}", file_arg);
-        
         let resolved_path = Self::resolve_file_path(file_arg)?;
-        println!("Resolved path before tagging: {}", resolved_path.display());
 
-        let mut items: Vec<TodoItem> = BufReader::new(File::open(&list_path)?)
-            .lines()
-            .filter_map(Result::ok)
-            .map(|line| TodoItem::from_string(&line))
-            .collect();
+        let mut items = self.read_items(list_name)?;
 
-        if task_num == 0 || task_num > items.len() {
-            println!("Error: Invalid task number");
-            return Ok(());
-        }
+        let indexes = match item::select(&items, selection) {
+            Ok(indexes) => indexes,
+            Err(e) => {
+                println!("Error: {}", e);
+                return Ok(());
+            }
+        };
 
-        items[task_num - 1].add_tag(resolved_path.clone());
-        
-        let mut file = File::create(&list_path)?;
-        for item in items {
-            writeln!(file, "{}", item.to_string())?;
+        for &index in &indexes {
+            items[index].add_tag(resolved_path.clone());
         }
+        self.write_items(list_name, &items)?;
 
-        println!("Tagged task {} in list '{}' with '{}'", 
-            task_num, 
-            list_name, 
-            resolved_path.display()
-        );
+        if indexes.len() == 1 {
+            println!("Tagged task {} in list '{}' with '{}'", 
+                selection, 
+                list_name, 
+                resolved_path.display()
+            );
+        } else {
+            println!("Tagged {} tasks in list '{}' with '{}'",
+                indexes.len(),
+                list_name,
+                resolved_path.display()
+            );
+        }
         Ok(())
     }
 
@@ -162,100 +350,217 @@ impl TodoApp {
         }
 
         println!("Tasks in list '{}':", list_name);
-        let file = File::open(file_path)?;
-        let reader = BufReader::new(file);
-
-        for (index, line) in reader.lines().enumerate() {
-            if let Ok(task) = line {
-                let item = TodoItem::from_string(&task);
-                println!("{}. {}", index + 1, item.to_string());
-            }
+        let lists = self.read_all_lists()?;
+        let items = self.read_items(list_name)?;
+        for (index, address) in item::addresses(&items).iter().enumerate() {
+            let item = &items[index];
+            let blocked = if deps::is_blocked(&lists, &items, index) { " (blocked)" } else { "" };
+            println!("{}{}. {}{}", "  ".repeat(item.depth), address, item.summary(), blocked);
         }
         Ok(())
     }
 
-    fn list_all_lists(&self) -> io::Result<()> {
-        println!("Available todo lists:");
-        for entry in fs::read_dir(&self.base_dir)? {
-            if let Ok(entry) = entry {
-                if let Some(file_name) = entry.path().file_stem() {
-                    if let Some(name) = file_name.to_str() {
-                        println!("- {}", name);
-                    }
-                }
+    fn sort_setting(list_name: &str) -> String {
+        format!("sort.{}", names::encode(list_name))
+    }
+
+    fn saved_sort(&self, list_name: &str) -> Option<SortKey> {
+        self.config
+            .get(&Self::sort_setting(list_name))
+            .and_then(SortKey::parse)
+    }
+
+    fn sort_list(&mut self, list_name: &str, key: &str, save: bool) -> io::Result<()> {
+        if !self.get_list_path(list_name).exists() {
+            println!("List '{}' not found.", list_name);
+            return Ok(());
+        }
+
+        if key == "none" {
+            self.config.unset(&Self::sort_setting(list_name))?;
+            println!("List '{}' is no longer kept sorted.", list_name);
+            return Ok(());
+        }
+        let key = match SortKey::parse(key) {
+            Some(key) => key,
+            None => {
+                println!("Error: Unknown sort '{}' (use priority, due or created)", key);
+                return Ok(());
             }
+        };
+
+        let mut items = self.read_items(list_name)?;
+        item::sort_tree(&mut items, key);
+        if save {
+            self.config.set(&Self::sort_setting(list_name), &key.to_string())?;
+            println!("List '{}' will be kept sorted by {}.", list_name, key);
         }
-        Ok(())
+        self.write_items(list_name, &items)?;
+        self.list_tasks(list_name)
     }
 
-    fn remove_task(&self, task_num: usize, list_name: &str) -> io::Result<()> {
-        let file_path = self.get_list_path(list_name);
-        if !file_path.exists() {
+    fn reorder_task(&mut self, task: &str, list_name: &str, position: Option<usize>) -> io::Result<()> {
+        if !self.get_list_path(list_name).exists() {
             println!("List '{}' not found.", list_name);
             return Ok(());
         }
 
-        let tasks: Vec<String> = BufReader::new(File::open(&file_path)?)
-            .lines()
-            .filter_map(Result::ok)
-            .collect();
+        let mut items = self.read_items(list_name)?;
+        let index = match item::resolve(&items, task) {
+            Some(index) => index,
+            None => {
+                println!("Error: Invalid task number");
+                return Ok(());
+            }
+        };
+
+        // A saved sort would undo the move on the next write
+        if let Some(key) = self.saved_sort(list_name) {
+            self.config.unset(&Self::sort_setting(list_name))?;
+            println!("Note: list '{}' is no longer kept sorted by {}.", list_name, key);
+        }
+
+        // No position means the bottom
+        let new_index = item::move_to_position(&mut items, index, position.unwrap_or(usize::MAX));
+        self.write_items(list_name, &items)?;
+
+        let address = &item::addresses(&items)[new_index];
+        println!("Task {} moved to {} in list '{}'", task, address, list_name);
+        Ok(())
+    }
 
-        if task_num == 0 || task_num > tasks.len() {
-            println!("Error: Invalid task number");
+    fn set_priority(&self, task: &str, list_name: &str, level: &str) -> io::Result<()> {
+        if !self.get_list_path(list_name).exists() {
+            println!("List '{}' not found.", list_name);
             return Ok(());
         }
 
-        let mut new_tasks = tasks.clone();
-        new_tasks.remove(task_num - 1);
+        let priority = if level == "none" {
+            None
+        } else {
+            match Priority::parse(level) {
+                Some(priority) => Some(priority),
+                None => {
+                    println!("Error: Invalid priority '{}' (use high, medium, low or none)", level);
+                    return Ok(());
+                }
+            }
+        };
+
+        let mut items = self.read_items(list_name)?;
+        let index = match item::resolve(&items, task) {
+            Some(index) => index,
+            None => {
+                println!("Error: Invalid task number");
+                return Ok(());
+            }
+        };
+
+        items[index].priority = priority;
+        self.write_items(list_name, &items)?;
 
-        let mut file = File::create(&file_path)?;
-        for task in new_tasks {
-            writeln!(file, "{}", task)?;
+        match priority {
+            Some(priority) => println!("Task {} in list '{}' is now {} priority", task, list_name, priority),
+            None => println!("Cleared priority of task {} in list '{}'", task, list_name),
         }
+        Ok(())
+    }
 
-        println!("Task {} removed from list '{}'", task_num, list_name);
+    fn list_all_lists(&self) -> io::Result<()> {
+        if self.project {
+            println!("Project todo lists in {}:", self.base_dir.display());
+        } else {
+            println!("Available todo lists:");
+        }
+        for name in self.list_names()? {
+            println!("- {}", name);
+        }
         Ok(())
     }
 
-    fn edit_task(&self, task_num: usize, new_text: &str, list_name: &str) -> io::Result<()> {
+    /// Names of every list in `base_dir`, sorted. Anything that isn't a list
+    /// file is ignored here and reported by `todo doctor` instead.
+    fn list_names(&self) -> io::Result<Vec<String>> {
+        let mut lists = Vec::new();
+        for entry in fs::read_dir(&self.base_dir)?.flatten() {
+            if !entry.file_type().is_ok_and(|t| t.is_file()) {
+                continue;
+            }
+            if let Some(name) = entry.file_name().to_str().and_then(names::list_name) {
+                lists.push(name);
+            }
+        }
+        lists.sort();
+        Ok(lists)
+    }
+
+    fn remove_task(&self, selection: &str, list_name: &str) -> io::Result<()> {
         let file_path = self.get_list_path(list_name);
         if !file_path.exists() {
             println!("List '{}' not found.", list_name);
             return Ok(());
         }
 
-        let mut tasks: Vec<String> = BufReader::new(File::open(&file_path)?)
-            .lines()
-            .filter_map(Result::ok)
-            .collect();
+        let mut items = self.read_items(list_name)?;
 
-        if task_num == 0 || task_num > tasks.len() {
-            println!("Error: Invalid task number");
-            return Ok(());
+        let indexes = match item::select(&items, selection) {
+            Ok(indexes) => indexes,
+            Err(e) => {
+                println!("Error: {}", e);
+                return Ok(());
+            }
+        };
+
+        // Removing a task takes its subtasks with it
+        let ranges = item::selected_subtrees(&items, &indexes);
+        let mut removed_items = Vec::new();
+        for &(start, end) in ranges.iter().rev() {
+            removed_items.extend(items.drain(start..end));
         }
+        item::roll_up(&mut items);
+        self.write_items(list_name, &items)?;
+        self.record_deleted(&removed_items)?;
+        let removed = removed_items.len();
 
-        tasks[task_num - 1] = new_text.to_string();
+        let subtasks = removed - ranges.len();
+        if ranges.len() == 1 && subtasks > 0 {
+            println!("Task {} and {} subtask(s) removed from list '{}'", selection, subtasks, list_name);
+        } else if ranges.len() == 1 {
+            println!("Task {} removed from list '{}'", selection, list_name);
+        } else if subtasks > 0 {
+            println!("Removed {} tasks and {} subtask(s) from list '{}'", ranges.len(), subtasks, list_name);
+        } else {
+            println!("Removed {} tasks from list '{}'", ranges.len(), list_name);
+        }
+        Ok(())
+    }
 
-        let mut file = File::create(&file_path)?;
-        for task in tasks {
-            writeln!(file, "{}", task)?;
+    fn edit_task(&self, task: &str, new_text: &str, list_name: &str) -> io::Result<()> {
+        let file_path = self.get_list_path(list_name);
+        if !file_path.exists() {
+            println!("List '{}' not found.", list_name);
+            return Ok(());
         }
 
-        println!("Task {} updated in list '{}'", task_num, list_name);
+        let mut items = self.read_items(list_name)?;
+
+        let index = match item::resolve(&items, task) {
+            Some(index) => index,
+            None => {
+                println!("Error: Invalid task number");
+                return Ok(());
+            }
+        };
+
+        items[index].text = new_text.to_string();
+        self.write_items(list_name, &items)?;
+
+        println!("Task {} updated in list '{}'", task, list_name);
         Ok(())
     }
 
     fn get_available_lists(&self) -> io::Result<Vec<String>> {
-        let mut lists = Vec::new();
-        for entry in fs::read_dir(&self.base_dir)? {
-            if let Ok(entry) = entry {
-                if let Some(file_name) = entry.path().file_stem() {
-                    if let Some(name) = file_name.to_str() {
-                        lists.push(name.to_string());
-                    }
-                }
-            }
-        }
+        let mut lists = self.list_names()?;
         if lists.is_empty() {
             lists.push("default".to_string());
         }
@@ -293,7 +598,7 @@ impl TodoApp {
         }
         
         // If not a number, use as list name
-        Ok(Some(input.to_string()))
+        Ok(Some(names::normalize(input)))
     }
 
     fn list_all_tasks(&self) -> io::Result<()> {
@@ -304,6 +609,7 @@ impl TodoApp {
             return Ok(());
         }
 
+        let all = self.read_all_lists()?;
         println!("\n=== All Todo Lists ===");
         for list_name in lists {
             println!("\n📋 {}", list_name);
@@ -315,16 +621,12 @@ impl TodoApp {
                 continue;
             }
 
-            let file = File::open(file_path)?;
-            let reader = BufReader::new(file);
-
-            let mut has_tasks = false;
-            for (index, line) in reader.lines().enumerate() {
-                if let Ok(task) = line {
-                    let item = TodoItem::from_string(&task);
-                    has_tasks = true;
-                    println!("  {}. {}", index + 1, item.to_string());
-                }
+            let items = &all[&list_name];
+            let has_tasks = !items.is_empty();
+            for (index, address) in item::addresses(items).iter().enumerate() {
+                let item = &items[index];
+                let blocked = if deps::is_blocked(&all, items, index) { " (blocked)" } else { "" };
+                println!("  {}{}. {}{}", "  ".repeat(item.depth), address, item.summary(), blocked);
             }
             
             if !has_tasks {
@@ -334,78 +636,446 @@ impl TodoApp {
         Ok(())
     }
 
-    fn copy_to_clipboard(text: &str) -> io::Result<bool> {
-        // Try xsel first
-        let xsel_result = Command::new("xsel")
-            .arg("--clipboard")
-            .arg("--input")
-            .stdin(Stdio::piped())
-            .spawn()
-            .and_then(|mut child| {
-                if let Some(stdin) = child.stdin.as_mut() {
-                    stdin.write_all(text.as_bytes())?;
-                }
-                child.wait().map(|_| true)
-            });
-
-        if xsel_result.is_ok() {
-            return Ok(true);
-        }
-
-        // Fallback to xclip
-        let xclip_result = Command::new("xclip")
-            .arg("-selection")
-            .arg("clipboard")
-            .stdin(Stdio::piped())
-            .spawn()
-            .and_then(|mut child| {
-                if let Some(stdin) = child.stdin.as_mut() {
-                    stdin.write_all(text.as_bytes())?;
-                }
-                child.wait().map(|_| true)
-            });
-
-        Ok(xclip_result.is_ok())
+    fn read_items(&self, list_name: &str) -> io::Result<Vec<TodoItem>> {
+        let file_path = self.get_list_path(list_name);
+        if !file_path.exists() {
+            return Ok(Vec::new());
+        }
+        // Read lossily so a stray invalid byte can't hide the rest of the list
+        let contents = fs::read(&file_path)?;
+        let mut items: Vec<TodoItem> = String::from_utf8_lossy(&contents)
+            .lines()
+            .map(TodoItem::from_string)
+            .collect();
+        item::normalize(&mut items);
+        // Tasks from before IDs existed get one the first time they're read
+        if item::assign_ids(&mut items) {
+            self.write_items(list_name, &items)?;
+        }
+        Ok(items)
     }
 
-    fn use_tag(&self, task_num: usize, list_name: &str, tag_num: Option<usize>) -> io::Result<()> {
-        let list_path = self.get_list_path(list_name);
-        if !list_path.exists() {
-            println!("List '{}' not found.", list_name);
-            return Ok(());
+    fn read_all_lists(&self) -> io::Result<Lists> {
+        let mut lists = Lists::new();
+        for list_name in self.get_available_lists()? {
+            let items = self.read_items(&list_name)?;
+            lists.insert(list_name, items);
         }
+        Ok(lists)
+    }
 
-        // Clone items at the start so we can modify them later if needed
-        let items: Vec<TodoItem> = BufReader::new(File::open(&list_path)?)
-            .lines()
-            .filter_map(Result::ok)
-            .map(|line| TodoItem::from_string(&line))
+    fn write_items(&self, list_name: &str, items: &[TodoItem]) -> io::Result<()> {
+        self.write_lists(&[(list_name, items)])
+    }
+
+    /// Writes several lists, marking the tasks that changed as updated now.
+    fn write_lists(&self, lists: &[(&str, &[TodoItem])]) -> io::Result<()> {
+        let mut stamped = Vec::new();
+        for (list_name, items) in lists {
+            let path = self.get_list_path(list_name);
+            let before = if path.exists() { merge::read_file(&path)? } else { Vec::new() };
+            let mut items = items.to_vec();
+            item::stamp_changes(&before, &mut items);
+            stamped.push((*list_name, items));
+        }
+        let stamped: Vec<(&str, &[TodoItem])> = stamped
+            .iter()
+            .map(|(name, items)| (*name, items.as_slice()))
             .collect();
+        self.store_lists(&stamped)
+    }
 
-        if task_num == 0 || task_num > items.len() {
-            println!("Error: Invalid task number");
-            return Ok(());
+    /// Writes several lists as they are, so that either all of them are
+    /// updated or none are: everything goes to temporary files first, which
+    /// are only renamed into place once they have all been written.
+    fn store_lists(&self, lists: &[(&str, &[TodoItem])]) -> io::Result<()> {
+        let mut pending = Vec::new();
+        let result = (|| {
+            for (list_name, items) in lists {
+                let path = self.get_list_path(list_name);
+                // Lists with a saved sort order are kept in that order
+                let mut items = items.to_vec();
+                if let Some(key) = self.saved_sort(list_name) {
+                    item::sort_tree(&mut items, key);
+                }
+                let temp_path = self.base_dir.join(format!(".{}.tmp", names::file_name(list_name)));
+                pending.push((temp_path.clone(), path));
+
+                let mut file = File::create(&temp_path)?;
+                for item in items.iter() {
+                    writeln!(file, "{}", item)?;
+                }
+                file.sync_all()?;
+            }
+            Ok(())
+        })();
+
+        if let Err(e) = result {
+            for (temp_path, _) in &pending {
+                let _ = fs::remove_file(temp_path);
+            }
+            return Err(e);
+        }
+        for (list_name, _) in lists {
+            self.snapshot(list_name)?;
+        }
+        for (temp_path, path) in pending {
+            fs::rename(temp_path, path)?;
         }
+        Ok(())
+    }
 
-        let item = &items[task_num - 1];
-        
-        if item.tags.is_empty() {
-            println!("No tags found for task {}.", task_num);
+    fn show_task(&self, task: &str, list_name: &str) -> io::Result<()> {
+        if !self.get_list_path(list_name).exists() {
+            println!("List '{}' not found.", list_name);
             return Ok(());
         }
 
-        // Verify path exists
-        let selected_tag = match tag_num {
-            Some(n) if n > 0 && n <= item.tags.len() => item.tags[n - 1].clone(),
-            Some(_) => {
-                println!("Invalid tag number. Available tags:");
-                for (i, tag) in item.tags.iter().enumerate() {
-                    println!("{}. {}", i + 1, tag.display());
-                }
+        let items = self.read_items(list_name)?;
+        let index = match item::resolve(&items, task) {
+            Some(index) => index,
+            None => {
+                println!("Error: Invalid task number");
                 return Ok(());
             }
-            None if item.tags.len() == 1 => item.tags[0].clone(),
-            None => {
+        };
+
+        let item = &items[index];
+        println!("Task {} in list '{}':", task, list_name);
+        println!("  {}", item.text);
+        println!("\nID: {}:{}", list_name, item.id);
+        println!("Status: {}", if item.done { "done" } else { "open" });
+        if let Some(priority) = item.priority {
+            println!("Priority: {}", priority);
+        }
+        if let Some(due) = item.due {
+            println!("Due: {}", due);
+        }
+        if let Some(recur) = item.recur {
+            println!("Repeats: {}", recur);
+        }
+        if let Some(created) = item.created {
+            println!("Created: {}", created);
+        }
+        if !item.depends.is_empty() {
+            let lists = self.read_all_lists()?;
+            let open = deps::open_prerequisites(&lists, &items, index);
+            println!("Depends on:");
+            for dep in &item.depends {
+                let state = match lists.get(&dep.list).and_then(|l| l.iter().find(|i| i.id == dep.id)) {
+                    Some(prerequisite) if prerequisite.done => "done".to_string(),
+                    Some(prerequisite) => format!("open: {}", prerequisite.text),
+                    None => "missing".to_string(),
+                };
+                println!("  {} ({})", dep, state);
+            }
+            if !item.done && !open.is_empty() {
+                println!("Blocked by: {}", open.iter().map(|d| d.to_string()).collect::<Vec<_>>().join(", "));
+            }
+        }
+        if item.tags.is_empty() {
+            println!("\nTags: none");
+        } else {
+            println!("\nTags:");
+            for (i, tag) in item.tags.iter().enumerate() {
+                println!("  {}. {}", i + 1, tag.display());
+            }
+        }
+        match &item.note {
+            Some(note) => {
+                println!("\nNote:");
+                for line in note.lines() {
+                    println!("  {}", line);
+                }
+            }
+            None => println!("\nNote: none"),
+        }
+        Ok(())
+    }
+
+    fn edit_note(&self, task: &str, list_name: &str) -> io::Result<()> {
+        if !self.get_list_path(list_name).exists() {
+            println!("List '{}' not found.", list_name);
+            return Ok(());
+        }
+
+        let mut items = self.read_items(list_name)?;
+        let index = match item::resolve(&items, task) {
+            Some(index) => index,
+            None => {
+                println!("Error: Invalid task number");
+                return Ok(());
+            }
+        };
+
+        // A fresh file of our own, so a planted symlink can't redirect it
+        let mut attempt = 0;
+        let (temp_path, mut temp_file) = loop {
+            let path = env::temp_dir().join(format!("todo-note-{}-{}.md", std::process::id(), attempt));
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => break (path, file),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists && attempt < 100 => attempt += 1,
+                Err(e) => return Err(e),
+            }
+        };
+        temp_file.write_all(items[index].note.as_deref().unwrap_or("").as_bytes())?;
+        drop(temp_file);
+
+        // $EDITOR may carry its own arguments, e.g. "code --wait"
+        let editor = env::var("VISUAL")
+            .or_else(|_| env::var("EDITOR"))
+            .unwrap_or_else(|_| "vi".to_string());
+        let mut parts = editor.split_whitespace();
+        let program = parts.next().unwrap_or("vi");
+        let status = Command::new(program)
+            .args(parts)
+            .arg(&temp_path)
+            .status();
+
+        let note = fs::read_to_string(&temp_path);
+        let _ = fs::remove_file(&temp_path);
+        match status {
+            Ok(status) if status.success() => {}
+            Ok(_) => {
+                println!("Editor exited with an error; note left unchanged.");
+                return Ok(());
+            }
+            Err(e) => {
+                println!("Could not start editor '{}': {}", program, e);
+                return Ok(());
+            }
+        }
+
+        let note = note?.trim_end().to_string();
+        items[index].note = if note.is_empty() { None } else { Some(note) };
+        self.write_items(list_name, &items)?;
+
+        if items[index].note.is_some() {
+            println!("Note saved for task {} in list '{}'", task, list_name);
+        } else {
+            println!("Note cleared for task {} in list '{}'", task, list_name);
+        }
+        Ok(())
+    }
+
+    /// The open tasks tagged for where the command runs: the current
+    /// directory, or the whole repository inside a git checkout, along with
+    /// anything beneath it and its ancestors. With `branch`, only tasks
+    /// tagged with that branch too. Grouped by list, as lines to print.
+    fn tasks_here(&self, current_dir: &Path, context: Option<&repo::Context>, branch: Option<&str>) -> io::Result<Vec<(String, Vec<String>)>> {
+        let dir = context.map_or(current_dir, |context| context.root.as_path());
+        let branch_tag = branch.map(repo::branch_tag);
+        let lists = self.read_all_lists()?;
+        let mut found = Vec::new();
+
+        for (list_name, items) in &lists {
+            let lines: Vec<String> = item::addresses(items)
+                .into_iter()
+                .enumerate()
+                .filter(|(index, _)| !items[*index].done)
+                .filter(|(index, _)| items[*index].tags.iter().any(|tag| {
+                    // A tag matches when it is the directory, something
+                    // beneath it, or one of its ancestors
+                    tag.starts_with(dir) || current_dir.starts_with(tag)
+                }))
+                .filter(|(index, _)| branch_tag.as_ref().is_none_or(|b| items[*index].tags.contains(b)))
+                .map(|(index, address)| {
+                    let blocked = if deps::is_blocked(&lists, items, index) { " (blocked)" } else { "" };
+                    format!("  {}. {}{}", address, items[index].summary(), blocked)
+                })
+                .collect();
+            if !lines.is_empty() {
+                found.push((list_name.clone(), lines));
+            }
+        }
+        Ok(found)
+    }
+
+    fn print_tasks_here(heading: &str, found: &[(String, Vec<String>)]) {
+        println!("{}", heading);
+        for (list_name, lines) in found {
+            println!("\n📋 {}", list_name);
+            println!("-------------------");
+            for line in lines {
+                println!("{}", line);
+            }
+        }
+    }
+
+    fn list_here(&self, branch_only: bool) -> io::Result<()> {
+        let current_dir = env::current_dir()?;
+        let context = repo::context(&current_dir);
+        let branch = match context.as_ref().and_then(|context| context.branch.as_deref()) {
+            Some(branch) if branch_only => Some(branch),
+            None if branch_only => {
+                println!("Error: --branch needs a git branch checked out");
+                return Ok(());
+            }
+            _ => None,
+        };
+        let dir = context.as_ref().map_or(current_dir.as_path(), |context| context.root.as_path());
+        let place = match branch {
+            Some(branch) => format!("{} on branch {}", dir.display(), branch),
+            None => dir.display().to_string(),
+        };
+
+        let found = self.tasks_here(&current_dir, context.as_ref(), branch)?;
+        if found.is_empty() {
+            println!("No tasks tagged for {}.", place);
+        } else {
+            Self::print_tasks_here(&format!("Tasks for {}:", place), &found);
+        }
+        Ok(())
+    }
+
+    /// After the list names, `todo list` inside a git checkout also shows
+    /// the tasks tagged to that repository.
+    fn list_repo_tasks(&self) -> io::Result<()> {
+        let current_dir = env::current_dir()?;
+        if let Some(context) = repo::context(&current_dir) {
+            let found = self.tasks_here(&current_dir, Some(&context), None)?;
+            if !found.is_empty() {
+                Self::print_tasks_here(&format!("\nTasks for {}:", context.root.display()), &found);
+            }
+        }
+        Ok(())
+    }
+
+    fn add_dependency(&self, task: &str, list_name: &str, prerequisite: &str, remove: bool) -> io::Result<()> {
+        if !self.get_list_path(list_name).exists() {
+            println!("List '{}' not found.", list_name);
+            return Ok(());
+        }
+
+        let lists = self.read_all_lists()?;
+        let mut items = self.read_items(list_name)?;
+        let index = match item::resolve(&items, task) {
+            Some(index) => index,
+            None => {
+                println!("Error: Invalid task number");
+                return Ok(());
+            }
+        };
+
+        if remove {
+            let before = items[index].depends.len();
+            let target = deps::resolve(&lists, prerequisite)
+                .or_else(|| TaskRef::parse(prerequisite));
+            items[index].depends.retain(|d| Some(d) != target.as_ref());
+            if items[index].depends.len() == before {
+                println!("Task {} in list '{}' does not depend on '{}'", task, list_name, prerequisite);
+                return Ok(());
+            }
+            self.write_items(list_name, &items)?;
+            println!("Task {} in list '{}' no longer depends on {}", task, list_name, prerequisite);
+            return Ok(());
+        }
+
+        let prerequisite = match deps::resolve(&lists, prerequisite) {
+            Some(prerequisite) => prerequisite,
+            None => {
+                println!("Error: No task '{}' (use <list>:<id> or <list>:<num>)", prerequisite);
+                return Ok(());
+            }
+        };
+        let this = TaskRef { list: list_name.to_string(), id: items[index].id.clone() };
+        if deps::creates_cycle(&lists, &this, &prerequisite) {
+            println!("Error: {} already depends on {}; adding this would create a cycle", prerequisite, this);
+            return Ok(());
+        }
+        if items[index].depends.contains(&prerequisite) {
+            println!("Task {} in list '{}' already depends on {}", task, list_name, prerequisite);
+            return Ok(());
+        }
+
+        items[index].depends.push(prerequisite.clone());
+        self.write_items(list_name, &items)?;
+        println!("Task {} in list '{}' now depends on {}", task, list_name, prerequisite);
+        Ok(())
+    }
+
+    fn list_next(&self, list_filter: Option<&str>) -> io::Result<()> {
+        let lists = self.read_all_lists()?;
+        let mut ready = Vec::new();
+
+        for (list_name, items) in &lists {
+            if list_filter.is_some_and(|filter| filter != list_name) {
+                continue;
+            }
+            let addresses = item::addresses(items);
+            for (index, item) in items.iter().enumerate() {
+                // Tasks with subtasks are finished by finishing the subtasks
+                let has_children = item::subtree_end(items, index) > index + 1;
+                if item.done || has_children || deps::is_blocked(&lists, items, index) {
+                    continue;
+                }
+                ready.push((item.due, list_name, addresses[index].clone(), item));
+            }
+        }
+
+        if ready.is_empty() {
+            println!("Nothing to do right now.");
+            return Ok(());
+        }
+
+        // Soonest due first; undated work keeps list order after that
+        ready.sort_by_key(|(due, _, _, _)| (due.is_none(), *due));
+        println!("Ready to work on:");
+        for (_, list_name, address, item) in ready {
+            println!("  {} {}. {}", list_name, address, item.summary());
+        }
+        Ok(())
+    }
+
+    fn copy_to_clipboard(&self, text: &str) -> Option<String> {
+        clipboard::copy(text, self.config.get("clipboard"))
+    }
+
+    fn use_tag(&self, task: &str, list_name: &str, tag_num: Option<usize>) -> io::Result<()> {
+        let list_path = self.get_list_path(list_name);
+        if !list_path.exists() {
+            println!("List '{}' not found.", list_name);
+            return Ok(());
+        }
+
+        let items = self.read_items(list_name)?;
+
+        let index = match item::resolve(&items, task) {
+            Some(index) => index,
+            None => {
+                println!("Error: Invalid task number");
+                return Ok(());
+            }
+        };
+
+        let item = &items[index];
+        
+        if item.tags.is_empty() {
+            println!("No tags found for task {}.", task);
+            return Ok(());
+        }
+
+        // Branch tags aren't places of their own; they say which branch to
+        // switch to in the task's repository
+        let branch = item.tags.iter().find_map(|tag| repo::parse_branch(tag));
+        let places: Vec<&PathBuf> = item.tags.iter().filter(|tag| item::is_path_tag(tag)).collect();
+
+        // Verify path exists
+        let selected_tag = match tag_num {
+            Some(n) if n > 0 && n <= item.tags.len() => item.tags[n - 1].clone(),
+            Some(_) => {
+                println!("Invalid tag number. Available tags:");
+                for (i, tag) in item.tags.iter().enumerate() {
+                    println!("{}. {}", i + 1, tag.display());
+                }
+                return Ok(());
+            }
+            None if places.len() == 1 => places[0].clone(),
+            None if places.is_empty() => {
+                println!("No paths tagged for task {}.", task);
+                return Ok(());
+            }
+            None => {
                 println!("Multiple tags available. Please specify tag number:");
                 for (i, tag) in item.tags.iter().enumerate() {
                     println!("{}. {}", i + 1, tag.display());
@@ -414,8 +1084,27 @@ impl TodoApp {
             }
         };
 
-        let path = PathBuf::from(&selected_tag);
-        
+        let selected_tag = match repo::parse_branch(&selected_tag) {
+            Some(_) => match places.iter().find(|tag| tag.is_dir()) {
+                Some(dir) => dir.to_path_buf(),
+                None => {
+                    println!("No repository tagged to switch branch in for task {}.", task);
+                    return Ok(());
+                }
+            },
+            None => selected_tag,
+        };
+        if !item::is_path_tag(&selected_tag) {
+            println!("Tag '{}' isn't a path.", selected_tag.display());
+            return Ok(());
+        }
+
+        // `<file>:<line>` tags from TODO comments point into a file
+        let path = match comments::parse_location(&selected_tag) {
+            Some((file, _)) if !selected_tag.exists() => file,
+            _ => PathBuf::from(&selected_tag),
+        };
+
         // Check if path exists
         if !path.exists() {
             println!("Warning: Path does not exist: {}", path.display());
@@ -424,11 +1113,8 @@ impl TodoApp {
             stdin().read_line(&mut input)?;
             if input.trim().to_lowercase() == "y" {
                 let mut items = items;
-                items[task_num - 1].tags.retain(|t| t != &selected_tag);
-                let mut file = File::create(&list_path)?;
-                for item in items {
-                    writeln!(file, "{}", item.to_string())?;
-                }
+                items[index].tags.retain(|t| t != &selected_tag);
+                self.write_items(list_name, &items)?;
                 println!("Tag removed.");
             }
             return Ok(());
@@ -436,7 +1122,14 @@ impl TodoApp {
 
         // Handle existing path
         if path.is_dir() {
-            let cd_command = format!("cd {}", path.display());
+            let mut cd_command = format!("cd {}", shell_quote(&path.to_string_lossy()));
+            if let Some(branch) = branch.filter(|_| repo::context(&path).is_some()) {
+                if !repo::is_valid_branch(&branch) {
+                    println!("Error: Tag 'branch:{}' isn't a valid branch name.", branch);
+                    return Ok(());
+                }
+                cd_command.push_str(&format!(" && git switch {}", shell_quote(&branch)));
+            }
             
             // If in eval mode, only output the command
             if std::env::args().any(|arg| arg == "--eval") {
@@ -445,60 +1138,375 @@ impl TodoApp {
             }
 
             // Try to copy to clipboard
-            let copied = Self::copy_to_clipboard(&cd_command)?;
+            let copied = self.copy_to_clipboard(&cd_command);
 
             // Show instructions
             println!("\nTo change directory, either:");
-            println!("1. Copy and paste this command{}:",
-                if copied { " (already copied to clipboard)" } else { "" });
+            match &copied {
+                Some(backend) => println!("1. Copy and paste this command (already copied to clipboard via {}):", backend),
+                None => println!("1. Copy and paste this command:"),
+            }
             println!("   {}", cd_command);
             println!("2. Or use: eval $(todo use --eval 1 1 in rust)");
             
         } else if path.is_file() {
-            let file_path = path.display().to_string();
-            let copied = Self::copy_to_clipboard(&file_path)?;
+            let file_path = selected_tag.display().to_string();
+            let copied = self.copy_to_clipboard(&file_path);
 
             println!("Selected path is a file: {}", file_path);
-            if copied {
-                println!("File path copied to clipboard!");
+            match copied {
+                Some(backend) => println!("File path copied to clipboard via {}!", backend),
+                None => println!("Could not copy to clipboard (set a command with 'todo config clipboard <cmd>')."),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn configure(&mut self, key: Option<&str>, value: Option<&str>) -> io::Result<()> {
+        match (key, value) {
+            (None, _) => {
+                for (key, value) in self.config.entries() {
+                    println!("{} = {}", key, value);
+                }
+            }
+            (Some(key), None) => match self.config.get(key) {
+                Some(value) => println!("{} = {}", key, value),
+                None => println!("'{}' is not set.", key),
+            },
+            (Some(key), Some("--unset")) => {
+                self.config.unset(key)?;
+                println!("Unset '{}'", key);
+            }
+            (Some(key), Some(value)) => {
+                self.config.set(key, value)?;
+                println!("Set '{}' to '{}'", key, value);
+            }
+        }
+        Ok(())
+    }
+
+    fn transfer_tasks(&self, selection: &str, from: &str, to: &str, copy: bool) -> io::Result<()> {
+        if !self.get_list_path(from).exists() {
+            println!("List '{}' not found.", from);
+            return Ok(());
+        }
+        if from == to {
+            println!("Error: Source and destination lists are the same");
+            return Ok(());
+        }
+        if !Self::check_new_list_name(to) {
+            return Ok(());
+        }
+
+        let mut source = self.read_items(from)?;
+        let indexes = match item::select(&source, selection) {
+            Ok(indexes) => indexes,
+            Err(e) => {
+                println!("Error: {}", e);
+                return Ok(());
+            }
+        };
+
+        // Each selected task brings its subtasks
+        let ranges = item::selected_subtrees(&source, &indexes);
+
+        let mut moved = Vec::new();
+        for &(start, end) in &ranges {
+            let base_depth = source[start].depth;
+            for item in &source[start..end] {
+                let mut item = item.clone();
+                item.depth -= base_depth;
+                if copy {
+                    item.id = item::new_id();
+                }
+                moved.push(item);
+            }
+        }
+        let count = ranges.len();
+
+        let mut destination = self.read_items(to)?;
+        destination.extend(moved.iter().cloned());
+        item::roll_up(&mut destination);
+
+        if copy {
+            self.write_items(to, &destination)?;
+            println!("Copied {} task(s) from '{}' to '{}'", count, from, to);
+            return Ok(());
+        }
+
+        for &(start, end) in ranges.iter().rev() {
+            source.drain(start..end);
+        }
+        item::roll_up(&mut source);
+
+        // Point dependencies on the moved tasks at their new list
+        let moved_ids: Vec<String> = moved.iter().map(|item| item.id.clone()).collect();
+        deps::retarget(&mut source, from, to, Some(&moved_ids));
+        deps::retarget(&mut destination, from, to, Some(&moved_ids));
+        let mut others = self.read_all_lists()?;
+        others.remove(from);
+        others.remove(to);
+        others.retain(|_, items| deps::retarget(items, from, to, Some(&moved_ids)));
+
+        let mut writes: Vec<(&str, &[TodoItem])> = vec![(from, &source), (to, &destination)];
+        writes.extend(others.iter().map(|(name, items)| (name.as_str(), items.as_slice())));
+        self.write_lists(&writes)?;
+
+        println!("Moved {} task(s) from '{}' to '{}'", count, from, to);
+        Ok(())
+    }
+
+    fn confirm(prompt: &str) -> io::Result<bool> {
+        println!("{} (y/N)", prompt);
+        stdout().flush()?;
+        let mut input = String::new();
+        stdin().read_line(&mut input)?;
+        Ok(input.trim().eq_ignore_ascii_case("y"))
+    }
+
+    /// Moves the saved sort order (if any) from one list to another.
+    fn rename_settings(&mut self, from: &str, to: Option<&str>) -> io::Result<()> {
+        let key = Self::sort_setting(from);
+        if let Some(sort) = self.config.get(&key).map(|s| s.to_string()) {
+            self.config.unset(&key)?;
+            if let Some(to) = to {
+                self.config.set(&Self::sort_setting(to), &sort)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn rename_list(&mut self, from: &str, to: &str) -> io::Result<()> {
+        if !self.get_list_path(from).exists() {
+            println!("List '{}' not found.", from);
+            return Ok(());
+        }
+        if !Self::check_new_list_name(to) {
+            return Ok(());
+        }
+        if self.get_list_path(to).exists() {
+            println!("Error: List '{}' already exists (use 'todo lists merge {} into {}')", to, from, to);
+            return Ok(());
+        }
+
+        let mut lists = self.read_all_lists()?;
+        let mut items = lists.remove(from).unwrap_or_default();
+        deps::retarget(&mut items, from, to, None);
+        lists.retain(|_, items| deps::retarget(items, from, to, None));
+
+        let mut writes: Vec<(&str, &[TodoItem])> = vec![(to, &items)];
+        writes.extend(lists.iter().map(|(name, items)| (name.as_str(), items.as_slice())));
+        self.write_lists(&writes)?;
+        self.snapshot(from)?;
+        fs::remove_file(self.get_list_path(from))?;
+        self.rename_settings(from, Some(to))?;
+
+        println!("List '{}' renamed to '{}'", from, to);
+        Ok(())
+    }
+
+    fn merge_lists(&mut self, from: &str, to: &str) -> io::Result<()> {
+        if !self.get_list_path(from).exists() {
+            println!("List '{}' not found.", from);
+            return Ok(());
+        }
+        if from == to {
+            println!("Error: Cannot merge a list into itself");
+            return Ok(());
+        }
+        if !Self::check_new_list_name(to) {
+            return Ok(());
+        }
+
+        let mut lists = self.read_all_lists()?;
+        let moved = lists.remove(from).unwrap_or_default();
+        let mut destination = lists.remove(to).unwrap_or_default();
+        let count = moved.len();
+        destination.extend(moved);
+        deps::retarget(&mut destination, from, to, None);
+        lists.retain(|_, items| deps::retarget(items, from, to, None));
+
+        let mut writes: Vec<(&str, &[TodoItem])> = vec![(to, &destination)];
+        writes.extend(lists.iter().map(|(name, items)| (name.as_str(), items.as_slice())));
+        self.write_lists(&writes)?;
+        self.snapshot(from)?;
+        fs::remove_file(self.get_list_path(from))?;
+        self.rename_settings(from, None)?;
+
+        println!("Merged {} task(s) from '{}' into '{}'", count, from, to);
+        Ok(())
+    }
+
+    fn archive_list(&self, list_name: &str, restore: bool) -> io::Result<()> {
+        let archive_dir = self.base_dir.join(ARCHIVE_DIR);
+        let archived_path = archive_dir.join(names::file_name(list_name));
+        let list_path = self.get_list_path(list_name);
+
+        let (from, to) = if restore {
+            (archived_path, list_path)
+        } else {
+            (list_path, archived_path)
+        };
+        if !from.exists() {
+            if restore {
+                println!("No archived list '{}'.", list_name);
+            } else {
+                println!("List '{}' not found.", list_name);
             }
+            return Ok(());
+        }
+        if to.exists() {
+            println!("Error: '{}' already exists in {}", list_name,
+                if restore { "your lists" } else { "the archive" });
+            return Ok(());
         }
 
+        fs::create_dir_all(&archive_dir)?;
+        fs::rename(&from, &to)?;
+        if restore {
+            println!("List '{}' restored from the archive", list_name);
+        } else {
+            println!("List '{}' archived", list_name);
+        }
+        Ok(())
+    }
+
+    fn list_archived(&self) -> io::Result<()> {
+        let archive_dir = self.base_dir.join(ARCHIVE_DIR);
+        println!("Archived lists:");
+        if archive_dir.is_dir() {
+            let mut archived: Vec<String> = fs::read_dir(&archive_dir)?
+                .flatten()
+                .filter_map(|entry| entry.file_name().to_str().and_then(names::list_name))
+                .collect();
+            archived.sort();
+            for name in archived {
+                println!("- {}", name);
+            }
+        }
+        Ok(())
+    }
+
+    fn delete_list(&mut self, list_name: &str, force: bool) -> io::Result<()> {
+        let list_path = self.get_list_path(list_name);
+        if !list_path.exists() {
+            println!("List '{}' not found.", list_name);
+            return Ok(());
+        }
+
+        let items = self.read_items(list_name)?;
+        if !force && !Self::confirm(&format!("Delete list '{}' and its {} task(s)?", list_name, items.len()))? {
+            println!("Operation cancelled");
+            return Ok(());
+        }
+
+        self.snapshot(list_name)?;
+        fs::remove_file(&list_path)?;
+        self.record_deleted(&items)?;
+        self.rename_settings(list_name, None)?;
+        println!("List '{}' deleted.", list_name);
         Ok(())
     }
 
     fn cleanup_list(&self, list_name: &str) -> io::Result<()> {
         let list_path = self.get_list_path(list_name);
         if list_path.exists() {
+            let items = self.read_items(list_name)?;
+            self.snapshot(list_name)?;
             fs::remove_file(&list_path)?;
+            self.record_deleted(&items)?;
             println!("List '{}' has been reset.", list_name);
         }
         Ok(())
     }
 }
 
+/// `s` as a single shell word, for commands the user pastes or `eval`s.
+fn shell_quote(s: &str) -> String {
+    let plain = !s.is_empty()
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || "/._-+=:@%,".contains(c));
+    if plain {
+        s.to_string()
+    } else {
+        format!("'{}'", s.replace('\'', "'\\''"))
+    }
+}
+
 fn print_usage() {
     println!("Usage:");
     println!("  todo add <task> to <list>     - Add a task to a specific list");
+    println!("  todo add <task> to <list> --branch - Add a task tagged with the git repository and branch");
     println!("  todo list                     - Show all available lists");
     println!("  todo list all                 - Show all lists and their tasks");
     println!("  todo list <list>              - List all tasks in a specific list");
-    println!("  todo remove <num> from <list> - Remove task by number from a list");
+    println!("  todo list <list> --sort <priority|due|created|none> [--save] - Sort a list, optionally keeping it sorted");
+    println!("  todo here [--branch]          - Show tasks tagged to the current directory or git repository");
+    println!("  todo add <task> under <num> in <list> - Add a subtask to a task");
+    println!("  todo next [<list>]            - Show open tasks that aren't blocked");
+    println!("  todo remove <nums> from <list> - Remove tasks by number from a list");
+    println!("      <num> may also be a selection: 1,3,7  2-5  3.1-3.4  done  open  all  tag:~/proj");
     println!("  todo edit <num> in <list> <new_text> - Edit a task in a list");
-    println!("  todo tag <num> in <list>            - Tag current directory to task");
-    println!("  todo tag <file> <num> in <list>     - Tag specific file to task");
+    println!("  todo done <nums> in <list>          - Mark tasks and their subtasks done");
+    println!("  todo reopen <nums> in <list>        - Mark tasks and their subtasks not done");
+    println!("  todo due <num> in <list> <date>     - Set or clear (none) a task's due date");
+    println!("  todo repeat <num> in <list> <rule>  - Repeat a task, e.g. every monday, every 2 weeks");
+    println!("  todo depend <num> in <list> on <list>:<id>   - Block a task until another is done");
+    println!("  todo undepend <num> in <list> on <list>:<id> - Remove a dependency");
+    println!("  todo priority <num> in <list> <level> - Set priority: high, medium, low or none");
+    println!("  todo mv <num> to <pos> in <list>    - Move a task to a position among its siblings");
+    println!("  todo top <num> in <list>            - Move a task to the top");
+    println!("  todo bottom <num> in <list>         - Move a task to the bottom");
+    println!("  todo show <num> in <list>           - Show a task's tags and note");
+    println!("  todo note <num> in <list>           - Edit a task's note in $EDITOR");
+    println!("  todo tag <nums> in <list>           - Tag current directory to tasks");
+    println!("  todo tag <file> <nums> in <list>    - Tag specific file to tasks");
     println!("  todo use <num> in <list>           - Use first/only tag of task");
     println!("  todo use <num> <tag_num> in <list> - Use specific tag of task");
+    println!("  todo move <nums> from <list> to <list> - Move tasks (e.g. 1,3 or 2-5) to another list");
+    println!("  todo copy <nums> from <list> to <list> - Copy tasks to another list");
     println!("  todo cleanup <list>               - Reset a specific list");
+    println!("  todo lists rename <a> <b>         - Rename a list");
+    println!("  todo lists merge <a> into <b>     - Move every task of one list into another");
+    println!("  todo lists archive [<list>]       - Archive a list, or show archived lists");
+    println!("  todo lists unarchive <list>       - Bring an archived list back");
+    println!("  todo lists delete <list> [--force] - Delete a list (asks first unless --force)");
+    println!("  todo backup list [<list>]         - Show the snapshots kept before each change");
+    println!("  todo backup restore <snapshot>    - Roll a list back to a snapshot");
+    println!("  todo merge <other-dir>            - Merge in lists from another copy of the todo directory");
+    println!("  todo sync init [<remote>]         - Track lists with git, committing every change");
+    println!("  todo sync                         - Merge with the remote (config sync.remote) and push");
+    println!("  todo serve [--port <port>]        - Serve a web UI and JSON API on localhost (default port 8080)");
+    println!("  todo ical <list|all> [--events]   - Print tasks as an iCalendar file, optionally with due-date events");
+    println!("  todo caldav sync [<list>]         - Two-way sync with CalDAV calendars (config caldav.url)");
+    println!("  todo scan <dir> into <list>       - Track the TODO, FIXME and HACK comments under a directory as tasks");
+    println!("  todo hook install                 - Close tasks from commits saying 'closes todo:<list>#<id>'");
+    println!("  todo lsp <list>                   - Language server showing tagged tasks and adding TODO comments to a list");
+    println!("  todo doctor [--fix]               - Check lists for problems, optionally repairing them");
+    println!("  todo config [<key> [<value>|--unset]] - Show or change settings");
+    println!("  todo project [init]               - Show which lists are in use, or give this project its own .todo lists");
+    println!("  Inside a project with a .todo directory, commands use its lists; add --global for your own.");
 }
 
 fn main() -> io::Result<()> {
     // Set backtrace at start of program
     std::env::set_var("RUST_BACKTRACE", "1");
     
-    let app = TodoApp::new()?;
     let args: Vec<String> = std::env::args().collect();
-    
+    // "--global" may go anywhere and skips any project's lists
+    let global = args.iter().any(|a| a == "--global");
+    let args: Vec<String> = args.into_iter().filter(|a| a != "--global").collect();
+    let mut app = TodoApp::new(global)?;
+
+    run(&mut app, &args)?;
+    // `sync` commits by itself, and must not commit while git is merging
+    if args.len() > 1 && args[1] != "sync" {
+        app.auto_commit(&args[1..]);
+    }
+    Ok(())
+}
+
+fn run(app: &mut TodoApp, args: &[String]) -> io::Result<()> {
     if args.len() < 2 {
         print_usage();
         return Ok(());
@@ -506,29 +1514,51 @@ fn main() -> io::Result<()> {
 
     match args[1].as_str() {
         "add" => {
+            // "--branch" tags the task with the repository and branch checked out
+            let branch = args.iter().any(|a| a == "--branch");
+            let args: Vec<String> = args.iter().filter(|a| *a != "--branch").cloned().collect();
             if args.len() < 3 {
-                println!("Usage: todo add <task> [to <list>]");
+                println!("Usage: todo add <task> [to <list>] [--branch]");
                 return Ok(());
             }
-            
+            let mut tags = Vec::new();
+            if branch {
+                match repo::context(&env::current_dir()?) {
+                    Some(repo::Context { root, branch: Some(branch) }) => {
+                        tags.push(root);
+                        tags.push(repo::branch_tag(&branch));
+                    }
+                    _ => {
+                        println!("Error: --branch needs a git branch checked out");
+                        return Ok(());
+                    }
+                }
+            }
+
             let has_list = args.windows(2).any(|w| w[0] == "to");
+            let n = args.len();
             
-            if has_list {
+            if n >= 7 && args[n-4] == "under" && args[n-2] == "in" {
+                // "todo add <task> under <num> in <list>"
+                // Leading spaces would read back as indentation
+                let task = args[2..n-4].join(" ").trim().to_string();
+                app.add_subtask(&task, &args[n-3], &names::normalize(&args[n-1]), &tags)?;
+            } else if has_list {
                 // Original behavior for "todo add <task> to <list>"
                 if args.len() < 5 || args[args.len()-2] != "to" {
                     println!("Usage: todo add <task> to <list>");
                     return Ok(());
                 }
-                let list_name = &args[args.len()-1];
-                let task = args[2..args.len()-2].join(" ");
-                app.add_task(&task, list_name)?;
+                let list_name = names::normalize(&args[args.len()-1]);
+                let task = args[2..args.len()-2].join(" ").trim().to_string();
+                app.add_task(&task, &list_name, &tags)?;
             } else {
                 // New interactive behavior when no list is specified
-                let task = args[2..].join(" ");
+                let task = args[2..].join(" ").trim().to_string();
                 
                 match app.prompt_for_list()? {
                     Some(list_name) => {
-                        app.add_task(&task, &list_name)?;
+                        app.add_task(&task, &list_name, &tags)?;
                     }
                     None => {
                         println!("Operation cancelled");
@@ -537,10 +1567,56 @@ fn main() -> io::Result<()> {
             }
         }
         "list" => {
+            if args.len() >= 5 && args[3] == "--sort" {
+                let save = args.get(5).is_some_and(|a| a == "--save");
+                app.sort_list(&names::normalize(&args[2]), &args[4], save)?;
+                return Ok(());
+            }
             match args.get(2).map(|s| s.as_str()) {
                 Some("all") => app.list_all_tasks()?,
-                Some(list_name) => app.list_tasks(list_name)?,
-                None => app.list_all_lists()?,
+                Some(list_name) => app.list_tasks(&names::normalize(list_name))?,
+                None => {
+                    app.list_all_lists()?;
+                    app.list_repo_tasks()?;
+                }
+            }
+        }
+        "here" => app.list_here(args.get(2).is_some_and(|a| a == "--branch"))?,
+        "mv" => {
+            if args.len() < 7 || args[3] != "to" || args[5] != "in" {
+                println!("Usage: todo mv <num> to <pos> in <list>");
+                return Ok(());
+            }
+            match args[4].parse::<usize>() {
+                Ok(position) if position > 0 && item::is_address(&args[2]) => {
+                    app.reorder_task(&args[2], &names::normalize(&args[6]), Some(position))?;
+                }
+                _ => println!("Error: Invalid task number"),
+            }
+        }
+        "top" | "bottom" => {
+            if args.len() < 5 || args[3] != "in" {
+                println!("Usage: todo {} <num> in <list>", args[1]);
+                return Ok(());
+            }
+            if item::is_address(&args[2]) {
+                let position = if args[1] == "top" { Some(1) } else { None };
+                app.reorder_task(&args[2], &names::normalize(&args[4]), position)?;
+            } else {
+                println!("Error: Invalid task number");
+            }
+        }
+        "next" => app.list_next(args.get(2).map(|s| names::normalize(s)).as_deref())?,
+        "depend" | "undepend" => {
+            let n = args.len();
+            if n < 7 || args[3] != "in" || args[n-2] != "on" {
+                println!("Usage: todo {} <num> in <list> on <list>:<id>", args[1]);
+                return Ok(());
+            }
+            if item::is_address(&args[2]) {
+                app.add_dependency(&args[2], &names::normalize(&args[4]), &args[n-1], args[1] == "undepend")?;
+            } else {
+                println!("Error: Invalid task number");
             }
         }
         "remove" => {
@@ -548,41 +1624,81 @@ fn main() -> io::Result<()> {
                 println!("Usage: todo remove <num> from <list>");
                 return Ok(());
             }
-            let list_name = &args[args.len()-1];
-            if let Ok(num) = args[2].parse::<usize>() {
-                app.remove_task(num, list_name)?;
+            // "todo remove 1 3 5 from <list>" works like "todo remove 1,3,5 from <list>"
+            let list_name = names::normalize(&args[args.len()-1]);
+            let selection = args[2..args.len()-2].join(",");
+            app.remove_task(&selection, &list_name)?;
+        }
+        "edit" => {
+            if args.len() < 6 || args[3] != "in" {
+                println!("Usage: todo edit <num> in <list> <new_text>");
+                return Ok(());
+            }
+            if item::is_address(&args[2]) {
+                let list_name = names::normalize(&args[4]);
+                let new_text = args[5..].join(" ").trim().to_string();
+                app.edit_task(&args[2], &new_text, &list_name)?;
             } else {
                 println!("Error: Invalid task number");
             }
         }
-        "edit" => {
+        "done" | "reopen" => {
+            if args.len() < 5 || args[args.len()-2] != "in" {
+                println!("Usage: todo {} <nums|done|open|tag:<path>> in <list>", args[1]);
+                return Ok(());
+            }
+            let list_name = names::normalize(&args[args.len()-1]);
+            let selection = args[2..args.len()-2].join(",");
+            app.set_done(&selection, &list_name, args[1] == "done")?;
+        }
+        "show" | "note" => {
+            if args.len() < 5 || args[3] != "in" {
+                println!("Usage: todo {} <num> in <list>", args[1]);
+                return Ok(());
+            }
+            if item::is_address(&args[2]) {
+                let list_name = names::normalize(&args[4]);
+                if args[1] == "show" {
+                    app.show_task(&args[2], &list_name)?;
+                } else {
+                    app.edit_note(&args[2], &list_name)?;
+                }
+            } else {
+                println!("Error: Invalid task number");
+            }
+        }
+        "due" | "repeat" | "priority" => {
             if args.len() < 6 || args[3] != "in" {
-                println!("Usage: todo edit <num> in <list> <new_text>");
+                match args[1].as_str() {
+                    "due" => println!("Usage: todo due <num> in <list> <YYYY-MM-DD|today|tomorrow|weekday|none>"),
+                    "repeat" => println!("Usage: todo repeat <num> in <list> <rule|none>"),
+                    _ => println!("Usage: todo priority <num> in <list> <high|medium|low|none>"),
+                }
                 return Ok(());
             }
-            if let Ok(num) = args[2].parse::<usize>() {
-                let list_name = &args[4];
-                let new_text = args[5..].join(" ");
-                app.edit_task(num, &new_text, list_name)?;
+            if item::is_address(&args[2]) {
+                let list_name = names::normalize(&args[4]);
+                let value = args[5..].join(" ");
+                match args[1].as_str() {
+                    "due" => app.set_due(&args[2], &list_name, &value)?,
+                    "repeat" => app.set_recurrence(&args[2], &list_name, &value)?,
+                    _ => app.set_priority(&args[2], &list_name, &value)?,
+                }
             } else {
                 println!("Error: Invalid task number");
             }
         }
         "tag" => {
             if args.len() >= 5 && args[args.len()-2] == "in" {
-                let list_name = &args[args.len()-1];
-                if let Ok(num) = args[args.len()-3].parse::<usize>() {
-                    // Get the file argument if it exists
-                    let file_arg = if args.len() > 5 {
-                        Some(args[2].as_str())
-                    } else {
-                        None
-                    };
-                    println!("Passing file argument: {:?}", file_arg);  // Debug print
-                    app.add_tag(file_arg, num, list_name)?;
+                let list_name = names::normalize(&args[args.len()-1]);
+                let selection = &args[args.len()-3];
+                // Get the file argument if it exists
+                let file_arg = if args.len() > 5 {
+                    Some(args[2].as_str())
                 } else {
-                    println!("Error: Invalid task number");
-                }
+                    None
+                };
+                app.add_tag(file_arg, selection, &list_name)?;
             } else {
                 println!("Usage: todo tag <file> <num> in <list>");
             }
@@ -592,23 +1708,152 @@ fn main() -> io::Result<()> {
                 println!("Usage: todo use <num> [tag_num] in <list>");
                 return Ok(());
             }
-            let list_name = &args[args.len()-1];
-            let task_num = args[2].parse::<usize>().unwrap_or(0);
+            let list_name = names::normalize(&args[args.len()-1]);
+            let task = &args[2];
             let tag_num = if args.len() > 5 {
                 args[3].parse::<usize>().ok()
             } else {
                 None
             };
-            app.use_tag(task_num, list_name, tag_num)?;
+            app.use_tag(task, &list_name, tag_num)?;
+        }
+        "move" | "copy" => {
+            let n = args.len();
+            if n < 7 || args[n-2] != "to" || args[n-4] != "from" {
+                println!("Usage: todo {} <nums> from <list> to <list>", args[1]);
+                return Ok(());
+            }
+            let selection = args[2..n-4].join(",");
+            app.transfer_tasks(&selection, &names::normalize(&args[n-3]), &names::normalize(&args[n-1]), args[1] == "copy")?;
+        }
+        "lists" => {
+            let force = args.iter().any(|a| a == "--force");
+            let args: Vec<String> = args.iter().filter(|a| *a != "--force").map(|a| names::normalize(a)).collect();
+            let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+            match args[2..] {
+                [] => app.list_all_lists()?,
+                ["rename", from, to] => app.rename_list(from, to)?,
+                ["merge", from, "into", to] => app.merge_lists(from, to)?,
+                ["archive"] => app.list_archived()?,
+                ["archive", list_name] => app.archive_list(list_name, false)?,
+                ["unarchive", list_name] => app.archive_list(list_name, true)?,
+                ["delete", list_name] => app.delete_list(list_name, force)?,
+                _ => {
+                    println!("Usage: todo lists [rename <a> <b> | merge <a> into <b> | archive [<list>] | unarchive <list> | delete <list> [--force]]");
+                }
+            }
+        }
+        "backup" => {
+            match args.get(2).map(|s| s.as_str()) {
+                Some("list") => app.list_backups(args.get(3).map(|s| names::normalize(s)).as_deref())?,
+                Some("restore") if args.len() == 4 => app.restore_backup(&names::normalize(&args[3]))?,
+                _ => println!("Usage: todo backup list [<list>] | todo backup restore <list>@<timestamp>"),
+            }
+        }
+        "merge" => {
+            if args.len() != 3 {
+                println!("Usage: todo merge <other-dir>");
+                return Ok(());
+            }
+            app.merge_dir(&args[2])?;
+        }
+        "serve" => {
+            let port = match args[2..] {
+                [] => Some(8080),
+                [ref flag, ref port] if flag == "--port" => port.parse().ok(),
+                _ => None,
+            };
+            match port {
+                Some(port) => app.serve(port)?,
+                None => println!("Usage: todo serve [--port <port>]"),
+            }
         }
+        "scan" => {
+            if args.len() != 5 || args[3] != "into" {
+                println!("Usage: todo scan <dir> into <list>");
+                return Ok(());
+            }
+            app.scan(&args[2], &names::normalize(&args[4]))?;
+        }
+        "hook" => match args.get(2).map(|s| s.as_str()) {
+            Some("install") => app.install_hooks()?,
+            // Called by the installed hooks themselves
+            Some("commit-msg") if args.len() == 4 => {
+                if !app.check_commit_message(&args[3])? {
+                    std::process::exit(1);
+                }
+            }
+            Some("post-commit") => app.close_from_commit()?,
+            _ => println!("Usage: todo hook install"),
+        },
+        "project" => match args.get(2).map(|s| s.as_str()) {
+            Some("init") => app.project_init()?,
+            None => app.project_status(),
+            _ => println!("Usage: todo project [init]"),
+        },
+        "lsp" => {
+            if args.len() != 3 {
+                println!("Usage: todo lsp <list>");
+                return Ok(());
+            }
+            // Checked up front: once running, stdout belongs to the protocol
+            let list_name = names::normalize(&args[2]);
+            if TodoApp::check_new_list_name(&list_name) {
+                app.lsp(&list_name)?;
+            }
+        }
+        "ical" => {
+            let events = args.iter().any(|a| a == "--events");
+            let args: Vec<&String> = args.iter().filter(|a| *a != "--events").collect();
+            if args.len() != 3 {
+                println!("Usage: todo ical <list|all> [--events]");
+                return Ok(());
+            }
+            app.export_ical(&names::normalize(args[2]), events)?;
+        }
+        "caldav" => {
+            match args.get(2).map(|s| s.as_str()) {
+                Some("sync") if args.len() <= 4 => app.caldav_sync(args.get(3).map(|s| names::normalize(s)).as_deref())?,
+                _ => println!("Usage: todo caldav sync [<list>]"),
+            }
+        }
+        "sync" => {
+            match args[2..].iter().map(|s| s.as_str()).collect::<Vec<_>>()[..] {
+                [] => app.sync()?,
+                ["init"] => app.sync_init(None)?,
+                ["init", remote] => app.sync_init(Some(remote))?,
+                ["merge-driver", base, ours, theirs] => sync::merge_driver(base, ours, theirs)?,
+                _ => println!("Usage: todo sync [init [<remote>]]"),
+            }
+        }
+        "doctor" => app.doctor(args.iter().any(|a| a == "--fix"))?,
         "cleanup" => {
             if args.len() < 3 {
                 println!("Usage: todo cleanup <list>");
                 return Ok(());
             }
-            app.cleanup_list(&args[2])?;
+            app.cleanup_list(&names::normalize(&args[2]))?;
+        }
+        "config" => {
+            let value = if args.len() > 3 { Some(args[3..].join(" ")) } else { None };
+            app.configure(args.get(2).map(|s| s.as_str()), value.as_deref())?;
         }
         _ => print_usage(),
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shell_quote_keeps_words_whole() {
+        assert_eq!(shell_quote("/home/me/code"), "/home/me/code");
+        assert_eq!(shell_quote("/home/me/my code"), "'/home/me/my code'");
+        assert_eq!(shell_quote("x;rm -rf ~"), "'x;rm -rf ~'");
+        assert_eq!(shell_quote("$(id)"), "'$(id)'");
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+        assert_eq!(shell_quote(""), "''");
+    }
+}
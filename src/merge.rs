@@ -0,0 +1,491 @@
+// merge.rs
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::deps::Lists;
+use crate::item::{self, TodoItem};
+use crate::{names, TodoApp};
+
+/// IDs of removed tasks, one per line, so merging with a copy that still
+/// has them doesn't bring them back.
+pub const TOMBSTONE_FILE: &str = ".deleted";
+
+/// Reads a list file as it is, without assigning IDs or writing anything.
+pub fn read_file(path: &Path) -> io::Result<Vec<TodoItem>> {
+    let contents = fs::read(path)?;
+    Ok(String::from_utf8_lossy(&contents)
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(TodoItem::from_string)
+        .collect())
+}
+
+/// Combines two versions of a task that both changed since `base`. Fields
+/// only one side changed take that side's value; when both changed the same
+/// field, `ours` wins, except that tags and dependencies are unioned and a
+/// task done on either side stays done.
+fn merge_item(base: &TodoItem, ours: &TodoItem, theirs: &TodoItem) -> TodoItem {
+    fn pick<T: Clone + PartialEq>(base: &T, ours: &T, theirs: &T) -> T {
+        if ours == base { theirs.clone() } else { ours.clone() }
+    }
+
+    let mut merged = ours.clone();
+    merged.text = pick(&base.text, &ours.text, &theirs.text);
+    merged.note = pick(&base.note, &ours.note, &theirs.note);
+    merged.due = pick(&base.due, &ours.due, &theirs.due);
+    merged.recur = pick(&base.recur, &ours.recur, &theirs.recur);
+    merged.priority = pick(&base.priority, &ours.priority, &theirs.priority);
+    merged.depth = pick(&base.depth, &ours.depth, &theirs.depth);
+    merged.updated = ours.updated.max(theirs.updated);
+    merged.done = if ours.done != base.done || theirs.done != base.done {
+        ours.done || theirs.done
+    } else {
+        base.done
+    };
+
+    // Unions, minus whatever one side removed and the other left alone
+    merged.tags = union_minus_removed(&base.tags, &ours.tags, &theirs.tags);
+    merged.depends = union_minus_removed(&base.depends, &ours.depends, &theirs.depends);
+    merged
+}
+
+fn union_minus_removed<T: Clone + PartialEq>(base: &[T], ours: &[T], theirs: &[T]) -> Vec<T> {
+    let mut merged: Vec<T> = Vec::new();
+    for value in ours.iter().chain(theirs) {
+        let removed = base.contains(value) && (!ours.contains(value) || !theirs.contains(value));
+        if !removed && !merged.contains(value) {
+            merged.push(value.clone());
+        }
+    }
+    merged
+}
+
+/// Combines two copies of a task without knowing what they started from:
+/// the more recently updated copy wins, tags and dependencies are unioned,
+/// and a task done in either copy stays done. Ties are broken by comparing
+/// the copies' text, so the result doesn't depend on argument order.
+fn converge_item(a: &TodoItem, b: &TodoItem) -> TodoItem {
+    let (newer, older) = if is_newer(b, a) { (b, a) } else { (a, b) };
+    let mut merged = newer.clone();
+    merged.done = a.done || b.done;
+    for tag in &older.tags {
+        if !merged.tags.contains(tag) {
+            merged.tags.push(tag.clone());
+        }
+    }
+    for dep in &older.depends {
+        if !merged.depends.contains(dep) {
+            merged.depends.push(dep.clone());
+        }
+    }
+    merged
+}
+
+fn is_newer(a: &TodoItem, b: &TodoItem) -> bool {
+    (a.updated, a.to_string()) > (b.updated, b.to_string())
+}
+
+/// Inserts `item`, which sits at `position` in `source`, into `merged`
+/// right after the nearest task before it in `source` that `merged` has.
+fn place(merged: &mut Vec<TodoItem>, source: &[TodoItem], position: usize, item: TodoItem) {
+    let predecessor = source[..position]
+        .iter()
+        .rev()
+        .find_map(|p| merged.iter().position(|m| m.id == p.id));
+    let index = match predecessor {
+        Some(p) if item.depth > merged[p].depth => p + 1,
+        Some(p) => item::subtree_end(merged, p),
+        None => 0,
+    };
+    merged.insert(index, item);
+}
+
+fn same(a: &TodoItem, b: &TodoItem) -> bool {
+    a.to_string() == b.to_string()
+}
+
+/// Three-way merge of a list keyed by task ID, as used when two machines
+/// edited the same list. Tasks added on either side are kept, tasks deleted
+/// on one side are dropped unless the other side edited them, and tasks
+/// edited on both sides are merged field by field. The result follows the
+/// order of `ours`, with tasks only `theirs` has placed after the task they
+/// followed there.
+pub fn three_way(base: &[TodoItem], ours: &[TodoItem], theirs: &[TodoItem]) -> Vec<TodoItem> {
+    // Tasks without IDs would all collide under the empty one
+    let [mut base, mut ours, mut theirs] = [base.to_vec(), ours.to_vec(), theirs.to_vec()];
+    for items in [&mut base, &mut ours, &mut theirs] {
+        item::assign_text_ids(items);
+    }
+    let (base, ours, theirs) = (base.as_slice(), ours.as_slice(), theirs.as_slice());
+
+    let base_by_id: HashMap<&str, &TodoItem> = base.iter().map(|i| (i.id.as_str(), i)).collect();
+    let theirs_by_id: HashMap<&str, &TodoItem> = theirs.iter().map(|i| (i.id.as_str(), i)).collect();
+    let ours_ids: Vec<&str> = ours.iter().map(|i| i.id.as_str()).collect();
+
+    let mut merged: Vec<TodoItem> = Vec::new();
+    for item in ours {
+        let id = item.id.as_str();
+        match (base_by_id.get(id), theirs_by_id.get(id)) {
+            // Added here, or unchanged there: keep ours
+            (None, None) => merged.push(item.clone()),
+            (None, Some(their)) => merged.push(merge_item(item, item, their)),
+            // Deleted there: drop it unless we edited it
+            (Some(base_item), None) => {
+                if !same(base_item, item) {
+                    merged.push(item.clone());
+                }
+            }
+            (Some(base_item), Some(their)) => merged.push(merge_item(base_item, item, their)),
+        }
+    }
+
+    // Tasks we don't have: new on their side, or deleted here
+    for (position, their) in theirs.iter().enumerate() {
+        let id = their.id.as_str();
+        if ours_ids.contains(&id) {
+            continue;
+        }
+        if let Some(base_item) = base_by_id.get(id) {
+            // Deleted here; keep it only if they edited it since
+            if same(base_item, their) {
+                continue;
+            }
+        }
+        place(&mut merged, theirs, position, their.clone());
+    }
+
+    item::normalize(&mut merged);
+    item::roll_up(&mut merged);
+    merged
+}
+
+/// Gives the tasks in `items` without an ID the ID of a task with the same
+/// text in `other`, another copy of the list, or else one made from their
+/// text, so tasks from before IDs existed line up with their copies instead
+/// of colliding under the empty ID.
+fn adopt_ids(items: &mut [TodoItem], other: &[TodoItem]) {
+    let mut taken: HashSet<String> = items.iter().map(|item| item.id.clone()).collect();
+    for item in items.iter_mut().filter(|item| item.id.is_empty()) {
+        let text = item.text.trim();
+        let twin = other.iter().find(|o| !o.id.is_empty() && o.text.trim() == text && !taken.contains(&o.id));
+        if let Some(twin) = twin {
+            item.id = twin.id.clone();
+            taken.insert(twin.id.clone());
+        }
+    }
+    item::assign_text_ids(items);
+}
+
+/// Merges two copies of every list, e.g. from two machines that were used
+/// offline, keyed by task ID. Each task ends up in the list its newer copy
+/// is in, merged as by `converge_item`, unless either side deleted it. Each
+/// list keeps the order of whichever copy of it changed last, with tasks
+/// only the other copy has placed after the task they followed there. The
+/// result is the same whichever copy is passed first.
+pub fn converge(a: &Lists, b: &Lists, deleted: &HashSet<String>) -> Lists {
+    let (mut a, mut b) = (a.clone(), b.clone());
+    for (list_name, items) in a.iter_mut() {
+        adopt_ids(items, b.get(list_name).map_or(&[], Vec::as_slice));
+    }
+    for (list_name, items) in b.iter_mut() {
+        adopt_ids(items, a.get(list_name).map_or(&[], Vec::as_slice));
+    }
+    let (a, b) = (&a, &b);
+
+    let mut tasks: HashMap<&str, (&str, TodoItem)> = HashMap::new();
+    for (list_name, items) in a.iter().chain(b) {
+        for item in items.iter().filter(|item| !deleted.contains(&item.id)) {
+            let entry = match tasks.remove(item.id.as_str()) {
+                Some((other_list, other)) => {
+                    let list_name = if is_newer(item, &other) { list_name.as_str() } else { other_list };
+                    (list_name, converge_item(item, &other))
+                }
+                None => (list_name.as_str(), item.clone()),
+            };
+            tasks.insert(item.id.as_str(), entry);
+        }
+    }
+
+    let list_names: BTreeSet<&String> = a.keys().chain(b.keys()).collect();
+    let mut merged_lists = Lists::new();
+    for list_name in list_names {
+        let empty = Vec::new();
+        let copies = [a.get(list_name).unwrap_or(&empty), b.get(list_name).unwrap_or(&empty)];
+        let latest = |items: &Vec<TodoItem>| {
+            let updated = items.iter().filter_map(|item| item.updated).max();
+            let text: Vec<String> = items.iter().map(|item| item.to_string()).collect();
+            (updated, text)
+        };
+        let (first, second) = if latest(copies[1]) > latest(copies[0]) {
+            (copies[1], copies[0])
+        } else {
+            (copies[0], copies[1])
+        };
+
+        let belongs = |item: &TodoItem| {
+            tasks.get(item.id.as_str()).filter(|(owner, _)| owner == list_name).map(|(_, merged)| {
+                TodoItem { depth: item.depth, ..merged.clone() }
+            })
+        };
+        let mut merged: Vec<TodoItem> = first.iter().filter_map(belongs).collect();
+        for (position, item) in second.iter().enumerate() {
+            if merged.iter().all(|m| m.id != item.id) {
+                if let Some(item) = belongs(item) {
+                    place(&mut merged, second, position, item);
+                }
+            }
+        }
+
+        item::normalize(&mut merged);
+        item::roll_up(&mut merged);
+        merged_lists.insert(list_name.clone(), merged);
+    }
+    merged_lists
+}
+
+fn read_tombstones(dir: &Path) -> io::Result<HashSet<String>> {
+    let path = dir.join(TOMBSTONE_FILE);
+    if !path.exists() {
+        return Ok(HashSet::new());
+    }
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .map(|line| line.trim().to_string())
+        .filter(|id| !id.is_empty())
+        .collect())
+}
+
+impl TodoApp {
+    /// Remembers that these tasks were removed, for `todo merge`.
+    pub(crate) fn record_deleted(&self, items: &[TodoItem]) -> io::Result<()> {
+        if items.is_empty() {
+            return Ok(());
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.base_dir.join(TOMBSTONE_FILE))?;
+        for item in items.iter().filter(|item| !item.id.is_empty()) {
+            writeln!(file, "{}", item.id)?;
+        }
+        Ok(())
+    }
+
+    /// Merges the lists in another todo directory into this one; see
+    /// `converge`. The other directory is only read.
+    pub(crate) fn merge_dir(&self, other_dir: &str) -> io::Result<()> {
+        let other_dir = item::expand_path(other_dir);
+        if !other_dir.is_dir() {
+            println!("Error: '{}' is not a directory", other_dir.display());
+            return Ok(());
+        }
+        if fs::canonicalize(&other_dir)? == fs::canonicalize(&self.base_dir)? {
+            println!("Error: Can't merge a directory with itself");
+            return Ok(());
+        }
+
+        let ours = self.read_all_lists()?;
+        let mut theirs = Lists::new();
+        for entry in fs::read_dir(&other_dir)?.flatten() {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            if let Some(list_name) = names::list_name(&file_name) {
+                theirs.insert(list_name, read_file(&entry.path())?);
+            }
+        }
+
+        let mut deleted = read_tombstones(&self.base_dir)?;
+        let their_deleted = read_tombstones(&other_dir)?;
+        let new_deleted: Vec<&String> = their_deleted.difference(&deleted).collect();
+        if !new_deleted.is_empty() {
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.base_dir.join(TOMBSTONE_FILE))?;
+            for id in new_deleted {
+                writeln!(file, "{}", id)?;
+            }
+        }
+        deleted.extend(their_deleted);
+
+        let merged = converge(&ours, &theirs, &deleted);
+        let before: HashMap<&str, String> = ours
+            .values()
+            .flatten()
+            .map(|item| (item.id.as_str(), item.to_string()))
+            .collect();
+        let after: HashSet<&str> = merged.values().flatten().map(|item| item.id.as_str()).collect();
+        let added = after.iter().filter(|id| !before.contains_key(*id)).count();
+        let removed = before.keys().filter(|id| !after.contains(*id)).count();
+        let updated = merged
+            .values()
+            .flatten()
+            .filter(|item| before.get(item.id.as_str()).is_some_and(|line| *line != item.to_string()))
+            .count();
+
+        let lines = |items: &[TodoItem]| items.iter().map(|item| item.to_string()).collect::<Vec<_>>();
+        let writes: Vec<(&str, &[TodoItem])> = merged
+            .iter()
+            .filter(|(list_name, items)| match ours.get(*list_name) {
+                Some(current) => lines(current) != lines(items),
+                // Lists only the other copy has are skipped once all their tasks are gone
+                None => !items.is_empty(),
+            })
+            .map(|(list_name, items)| (list_name.as_str(), items.as_slice()))
+            .collect();
+        self.store_lists(&writes)?;
+
+        println!(
+            "Merged {}: {} task(s) added, {} updated, {} removed",
+            other_dir.display(), added, updated, removed
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn items(lines: &[&str]) -> Vec<TodoItem> {
+        lines.iter().map(|line| TodoItem::from_string(line)).collect()
+    }
+
+    fn texts(items: &[TodoItem]) -> Vec<String> {
+        items.iter().map(|item| format!("{}{}", "  ".repeat(item.depth), item.text)).collect()
+    }
+
+    #[test]
+    fn three_way_keeps_additions_from_both_sides() {
+        let base = items(&["a [[ ID : a1 ]]", "b [[ ID : b1 ]]"]);
+        let ours = items(&["a [[ ID : a1 ]]", "ours [[ ID : o1 ]]", "b [[ ID : b1 ]]"]);
+        let theirs = items(&["a [[ ID : a1 ]]", "b [[ ID : b1 ]]", "theirs [[ ID : t1 ]]"]);
+        let merged = three_way(&base, &ours, &theirs);
+        assert_eq!(texts(&merged), ["a", "ours", "b", "theirs"]);
+    }
+
+    #[test]
+    fn three_way_drops_deletions_unless_edited() {
+        let base = items(&["a [[ ID : a1 ]]", "b [[ ID : b1 ]]", "c [[ ID : c1 ]]"]);
+        let ours = items(&["a [[ ID : a1 ]]", "c [[ ID : c1 ]]"]);
+        let theirs = items(&["a [[ ID : a1 ]]", "b [[ ID : b1 ]]", "c edited [[ ID : c1 ]]"]);
+        assert_eq!(texts(&three_way(&base, &ours, &theirs)), ["a", "c edited"]);
+
+        // Deleted there, but edited here
+        let theirs = items(&["a [[ ID : a1 ]]"]);
+        let ours = items(&["a [[ ID : a1 ]]", "b [[ ID : b1 ]] [[ DONE ]]", "c [[ ID : c1 ]]"]);
+        let merged = three_way(&base, &ours, &theirs);
+        assert_eq!(texts(&merged), ["a", "b"]);
+        assert!(merged[1].done);
+    }
+
+    #[test]
+    fn three_way_merges_fields_edited_on_both_sides() {
+        let base = items(&["task [[ ID : t1 ]] [[ TAGS : /a ]]"]);
+        let ours = items(&["task renamed [[ ID : t1 ]] [[ TAGS : /a | /b ]]"]);
+        let theirs = items(&["task [[ ID : t1 ]] [[ DONE ]] [[ TAGS : /c ]]"]);
+        let merged = three_way(&base, &ours, &theirs);
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged[0].text, "task renamed");
+        assert!(merged[0].done);
+        assert_eq!(merged[0].tags, [PathBuf::from("/b"), PathBuf::from("/c")]);
+    }
+
+    #[test]
+    fn three_way_places_their_subtasks_under_their_parent() {
+        let base = items(&["a [[ ID : a1 ]]", "b [[ ID : b1 ]]"]);
+        let ours = base.clone();
+        let theirs = items(&["a [[ ID : a1 ]]", "  a sub [[ ID : s1 ]]", "b [[ ID : b1 ]]"]);
+        assert_eq!(texts(&three_way(&base, &ours, &theirs)), ["a", "  a sub", "b"]);
+    }
+
+    #[test]
+    fn three_way_keeps_tasks_without_ids_apart() {
+        let base = items(&["a", "b", "b"]);
+        let ours = items(&["a", "b", "b", "c"]);
+        let theirs = items(&["a edited", "b", "b"]);
+        let merged = three_way(&base, &ours, &theirs);
+        assert_eq!(texts(&merged), ["a edited", "b", "b", "c"]);
+        assert!(merged.iter().all(|item| !item.id.is_empty()));
+    }
+
+    fn lists(lists: &[(&str, &[&str])]) -> Lists {
+        lists.iter().map(|(name, lines)| (name.to_string(), items(lines))).collect()
+    }
+
+    fn list_texts(lists: &Lists) -> Vec<(String, Vec<String>)> {
+        lists.iter().map(|(name, items)| (name.clone(), texts(items))).collect()
+    }
+
+    #[test]
+    fn converge_keeps_the_newer_copy_of_each_task() {
+        let a = lists(&[("work", &[
+            "old [[ ID : t1 ]] [[ UPDATED : 2024-01-01 10:00:00 ]] [[ TAGS : /a ]]",
+            "only a [[ ID : a1 ]]",
+        ])]);
+        let b = lists(&[("work", &[
+            "new [[ ID : t1 ]] [[ UPDATED : 2024-01-02 10:00:00 ]] [[ TAGS : /b ]]",
+            "only b [[ ID : b1 ]] [[ DONE ]]",
+        ])]);
+        let merged = converge(&a, &b, &HashSet::new());
+        let work = &merged["work"];
+        // b changed last, so its order wins; "only a" follows the task it
+        // followed in a
+        assert_eq!(texts(work), ["new", "only a", "only b"]);
+        assert_eq!(work[0].tags, [PathBuf::from("/b"), PathBuf::from("/a")]);
+        assert!(work[2].done);
+    }
+
+    #[test]
+    fn converge_is_symmetric() {
+        let a = lists(&[
+            ("home", &["x [[ ID : x1 ]] [[ UPDATED : 2024-01-01 10:00:00 ]]", "y [[ ID : y1 ]]"]),
+            ("work", &["z [[ ID : z1 ]] [[ UPDATED : 2024-01-01 09:00:00 ]]"]),
+        ]);
+        let b = lists(&[
+            ("home", &["y [[ ID : y1 ]]", "x done [[ ID : x1 ]] [[ DONE ]] [[ UPDATED : 2024-01-03 10:00:00 ]]"]),
+            ("work", &["z moved [[ ID : z1 ]] [[ UPDATED : 2024-01-02 09:00:00 ]]"]),
+        ]);
+        let none = HashSet::new();
+        let forward = converge(&a, &b, &none);
+        let backward = converge(&b, &a, &none);
+        assert_eq!(list_texts(&forward), list_texts(&backward));
+        assert_eq!(texts(&forward["home"]), ["y", "x done"]);
+        assert_eq!(texts(&forward["work"]), ["z moved"]);
+    }
+
+    #[test]
+    fn converge_moves_tasks_to_the_list_of_their_newer_copy() {
+        let a = lists(&[("home", &["t [[ ID : t1 ]] [[ UPDATED : 2024-01-01 10:00:00 ]]"]), ("work", &[])]);
+        let b = lists(&[("home", &[]), ("work", &["t [[ ID : t1 ]] [[ UPDATED : 2024-01-02 10:00:00 ]]"])]);
+        let merged = converge(&a, &b, &HashSet::new());
+        assert!(merged["home"].is_empty());
+        assert_eq!(texts(&merged["work"]), ["t"]);
+    }
+
+    #[test]
+    fn converge_drops_deleted_tasks() {
+        let a = lists(&[("work", &["keep [[ ID : k1 ]]", "gone [[ ID : g1 ]]"])]);
+        let b = lists(&[("work", &["keep [[ ID : k1 ]]"])]);
+        let deleted: HashSet<String> = ["g1".to_string()].into();
+        assert_eq!(texts(&converge(&a, &b, &deleted)["work"]), ["keep"]);
+    }
+
+    #[test]
+    fn converge_matches_tasks_without_ids_by_text() {
+        let a = lists(&[("work", &["a [[ ID : a1 ]]", "b [[ ID : b1 ]]"])]);
+        let b = lists(&[("work", &["a", "b", "c", "c"])]);
+        let merged = converge(&a, &b, &HashSet::new());
+        assert_eq!(texts(&merged["work"]), ["a", "b", "c", "c"]);
+        assert_eq!(merged["work"][0].id, "a1");
+        assert_ne!(merged["work"][2].id, merged["work"][3].id);
+
+        let a = lists(&[("work", &["x", "y"])]);
+        let b = lists(&[("work", &["x", "y", "z"])]);
+        assert_eq!(texts(&converge(&a, &b, &HashSet::new())["work"]), ["x", "y", "z"]);
+    }
+}
@@ -0,0 +1,204 @@
+// names.rs
+
+use unicode_normalization::UnicodeNormalization;
+
+/// Longest list name we accept, in bytes once encoded, leaving room for the
+/// `.txt` extension and temporary-file prefixes within filesystem limits.
+const MAX_ENCODED_LEN: usize = 200;
+
+/// Names Windows refuses as file names whatever the extension.
+const RESERVED_NAMES: &[&str] = &[
+    "con", "prn", "aux", "nul",
+    "com1", "com2", "com3", "com4", "com5", "com6", "com7", "com8", "com9",
+    "lpt1", "lpt2", "lpt3", "lpt4", "lpt5", "lpt6", "lpt7", "lpt8", "lpt9",
+];
+
+/// Trims a list name and puts it in Unicode NFC form, so names that look
+/// the same always map to the same file.
+pub fn normalize(name: &str) -> String {
+    name.trim().nfc().collect()
+}
+
+/// Checks that `name` can be used for a new list.
+pub fn validate(name: &str) -> Result<(), String> {
+    let name = normalize(name);
+    if name.is_empty() {
+        return Err("list name is empty".to_string());
+    }
+    if name.chars().any(char::is_control) {
+        return Err("list name contains control characters".to_string());
+    }
+    if encode(&name).len() > MAX_ENCODED_LEN {
+        return Err("list name is too long".to_string());
+    }
+    Ok(())
+}
+
+fn is_reserved(c: char) -> bool {
+    matches!(c, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' | '%' | '[' | ']')
+        || c.is_control()
+}
+
+/// Encodes a list name as a file stem that is safe on every platform and
+/// can't leave the storage directory: path separators and other reserved
+/// characters become `%XX`, as does a leading dot. Other Unicode is kept.
+pub fn encode(name: &str) -> String {
+    let name = normalize(name);
+    let mut out = String::with_capacity(name.len());
+    for (i, c) in name.chars().enumerate() {
+        if is_reserved(c) || (i == 0 && c == '.') {
+            let mut buf = [0; 4];
+            for byte in c.encode_utf8(&mut buf).bytes() {
+                out.push_str(&format!("%{:02X}", byte));
+            }
+        } else {
+            out.push(c);
+        }
+    }
+
+    let lower = out.to_lowercase();
+    if RESERVED_NAMES.contains(&lower.as_str()) {
+        let first = out.remove(0);
+        out.insert_str(0, &format!("%{:02X}", first as u32));
+    }
+    out
+}
+
+/// Reverses `encode`. Malformed escapes are kept as they are.
+pub fn decode(stem: &str) -> String {
+    let bytes = stem.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let is_escape = bytes[i] == b'%'
+            && i + 2 < bytes.len()
+            && bytes[i + 1].is_ascii_hexdigit()
+            && bytes[i + 2].is_ascii_hexdigit();
+        if is_escape {
+            if let Ok(byte) = u8::from_str_radix(&stem[i + 1..i + 3], 16) {
+                out.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Percent-encodes a name for use as one URL path segment, leaving only
+/// ASCII letters, digits and `-._~`.
+pub fn url_segment(name: &str) -> String {
+    let mut out = String::new();
+    for byte in normalize(name).bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            out.push(byte as char);
+        } else {
+            out.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    out
+}
+
+/// The file name of a list's storage file.
+pub fn file_name(list_name: &str) -> String {
+    format!("{}.txt", encode(list_name))
+}
+
+/// The list stored in a file called `file_name`, if it is a list file at
+/// all: it must end in `.txt` and be exactly what `file_name` would produce,
+/// which rules out hidden files, editor backups and temporary files.
+pub fn list_name(file_name: &str) -> Option<String> {
+    let stem = file_name.strip_suffix(".txt")?;
+    if stem.is_empty() {
+        return None;
+    }
+    let name = decode(stem);
+    if encode(&name) == stem && validate(&name).is_ok() {
+        Some(name)
+    } else {
+        None
+    }
+}
+
+/// The list held by a file written before names were encoded, when the file
+/// isn't a list file by today's rules. Those files were named after the list
+/// exactly as typed, so `home:bills.txt` holds the list `home:bills`.
+pub fn legacy_list_name(file_name: &str) -> Option<String> {
+    let stem = file_name.strip_suffix(".txt")?;
+    if list_name(file_name).is_some() || validate(stem).is_err() {
+        return None;
+    }
+    Some(normalize(stem))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_reserved_characters() {
+        assert_eq!(encode("work"), "work");
+        assert_eq!(encode("home/bills"), "home%2Fbills");
+        assert_eq!(encode("50% done"), "50%25 done");
+        assert_eq!(encode("[a]:b"), "%5Ba%5D%3Ab");
+        assert_eq!(encode(".hidden"), "%2Ehidden");
+        assert_eq!(encode("a.b"), "a.b");
+        assert_eq!(encode("CON"), "%43ON");
+        assert_eq!(encode("console"), "console");
+    }
+
+    #[test]
+    fn decode_reverses_encode() {
+        for name in ["work", "home/bills", "50% done", "..", ".hidden", "a\\b", "nul", "Lpt1", "naïve 日本 🎉", "%41"] {
+            assert_eq!(decode(&encode(name)), name);
+            assert_eq!(list_name(&file_name(name)), Some(name.to_string()));
+        }
+    }
+
+    #[test]
+    fn decode_keeps_malformed_escapes() {
+        assert_eq!(decode("50%"), "50%");
+        assert_eq!(decode("%zz%4"), "%zz%4");
+    }
+
+    #[test]
+    fn normalizes_unicode_forms() {
+        let decomposed = "cafe\u{301}";
+        assert_eq!(normalize(decomposed), "caf\u{e9}");
+        assert_eq!(encode(decomposed), encode("caf\u{e9}"));
+        assert_eq!(normalize("  work "), "work");
+    }
+
+    #[test]
+    fn validates_names() {
+        assert!(validate("groceries").is_ok());
+        assert!(validate("   ").is_err());
+        assert!(validate("a\nb").is_err());
+        assert!(validate(&"x".repeat(201)).is_err());
+        assert!(validate(&"/".repeat(70)).is_err());
+    }
+
+    #[test]
+    fn only_encoded_names_are_list_files() {
+        assert_eq!(list_name("work.txt"), Some("work".to_string()));
+        assert_eq!(list_name("work.md"), None);
+        assert_eq!(list_name(".txt"), None);
+        assert_eq!(list_name(".work.txt.tmp"), None);
+        assert_eq!(list_name("50%.txt"), None);
+        assert_eq!(list_name("cafe\u{301}.txt"), None);
+    }
+
+    #[test]
+    fn recognizes_legacy_list_files() {
+        assert_eq!(legacy_list_name("home:bills.txt"), Some("home:bills".to_string()));
+        assert_eq!(legacy_list_name("50%.txt"), Some("50%".to_string()));
+        assert_eq!(legacy_list_name("[x].txt"), Some("[x]".to_string()));
+        assert_eq!(legacy_list_name(".plan.txt"), Some(".plan".to_string()));
+        assert_eq!(legacy_list_name("cafe\u{301}.txt"), Some("caf\u{e9}".to_string()));
+        assert_eq!(legacy_list_name("work.txt"), None);
+        assert_eq!(legacy_list_name(".work.txt.tmp"), None);
+        assert_eq!(legacy_list_name(" .txt"), None);
+    }
+}
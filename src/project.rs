@@ -0,0 +1,59 @@
+// project.rs
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::{repo, TodoApp};
+
+/// The directory holding a project's own lists, next to its code.
+pub const PROJECT_DIR: &str = ".todo";
+
+/// The nearest project directory at or above `start`, found the way git
+/// finds `.git`. The home directory itself is skipped, so `~/.todo` can't
+/// capture everything beneath it.
+pub fn find(start: &Path, home: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .filter(|dir| *dir != home)
+        .map(|dir| dir.join(PROJECT_DIR))
+        .find(|dir| dir.is_dir())
+}
+
+impl TodoApp {
+    /// Creates a project directory at the root of the current git checkout,
+    /// or in the current directory outside of one.
+    pub(crate) fn project_init(&mut self) -> io::Result<()> {
+        let current_dir = env::current_dir()?;
+        let root = repo::context(&current_dir).map_or(current_dir, |context| context.root);
+        let dir = root.join(PROJECT_DIR);
+        if dir.is_dir() {
+            println!("{} already exists.", dir.display());
+            return Ok(());
+        }
+        fs::create_dir_all(&dir)?;
+        self.base_dir = dir;
+        self.project = true;
+
+        // Lists committed with the code merge by task ID like synced ones
+        self.write_git_files()?;
+        if repo::context(&self.base_dir).is_some() {
+            self.install_merge_driver()?;
+        }
+        println!("Created {} for this project's lists.", self.base_dir.display());
+        println!("Commands run beneath {} now use them; add --global for your own lists.", root.display());
+        println!("Sort orders set there apply to this project only; other settings stay your own.");
+        Ok(())
+    }
+
+    pub(crate) fn project_status(&self) {
+        if self.project {
+            println!("Using project lists in {}", self.base_dir.display());
+            println!("Add --global to a command to use your own lists instead.");
+        } else {
+            println!("Using your own lists in {}", self.base_dir.display());
+            println!("Run 'todo project init' to give the current project lists of its own.");
+        }
+    }
+}
@@ -0,0 +1,266 @@
+// recur.rs
+
+use std::fmt;
+
+use chrono::{Datelike, Days, Local, Months, NaiveDate, Weekday};
+
+/// How often a task comes back once it is completed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Recurrence {
+    Days(u32),
+    Weeks(u32),
+    Months(u32),
+    /// Every week on the given day.
+    On(Weekday),
+    /// Every month on the given day of the month.
+    MonthlyOn(u32),
+}
+
+/// The longest interval a rule may give, so date arithmetic stays in range.
+pub const MAX_INTERVAL: u32 = 1000;
+
+impl Recurrence {
+    /// Parses rules like `every day`, `every monday`, `every 2 weeks` or
+    /// `monthly on 1st`. The leading `every` is optional.
+    pub fn parse(rule: &str) -> Option<Self> {
+        let rule = rule.trim().to_lowercase();
+        let words: Vec<&str> = rule.split_whitespace().collect();
+
+        match words.as_slice() {
+            ["daily"] => Some(Recurrence::Days(1)),
+            ["weekly"] => Some(Recurrence::Weeks(1)),
+            ["monthly"] => Some(Recurrence::Months(1)),
+            ["monthly", "on", day] | ["monthly", "on", "the", day] => {
+                parse_day_of_month(day).map(Recurrence::MonthlyOn)
+            }
+            ["every", rest @ ..] => match rest {
+                [unit] => parse_unit(1, unit),
+                [n, unit] => n
+                    .parse()
+                    .ok()
+                    .filter(|n| (1..=MAX_INTERVAL).contains(n))
+                    .and_then(|n| parse_unit(n, unit)),
+                _ => None,
+            },
+            [unit] => parse_unit(1, unit),
+            _ => None,
+        }
+    }
+
+    /// The first date strictly after `date` on which the task recurs, or
+    /// `None` past the last date chrono can represent.
+    pub fn next_after(&self, date: NaiveDate) -> Option<NaiveDate> {
+        match *self {
+            Recurrence::Days(n) => date.checked_add_days(Days::new(n as u64)),
+            Recurrence::Weeks(n) => date.checked_add_days(Days::new(7 * n as u64)),
+            Recurrence::Months(n) => date.checked_add_months(Months::new(n)),
+            Recurrence::On(weekday) => {
+                let ahead = (7 + weekday.num_days_from_monday()
+                    - date.weekday().num_days_from_monday()) % 7;
+                date.checked_add_days(Days::new(if ahead == 0 { 7 } else { ahead as u64 }))
+            }
+            Recurrence::MonthlyOn(day) => {
+                let this_month = clamp_to_month(date.year(), date.month(), day);
+                if this_month > date {
+                    return Some(this_month);
+                }
+                let next = date.with_day(1)?.checked_add_months(Months::new(1))?;
+                Some(clamp_to_month(next.year(), next.month(), day))
+            }
+        }
+    }
+
+    /// The due date for the next occurrence of a task that was due on
+    /// `due`. Occurrences that would already be in the past are skipped.
+    pub fn next_due(&self, due: Option<NaiveDate>) -> Option<NaiveDate> {
+        let today = today();
+        let mut next = self.next_after(due.unwrap_or(today))?;
+        while next <= today {
+            next = self.next_after(next)?;
+        }
+        Some(next)
+    }
+
+    /// The first date on or after today on which the task recurs.
+    pub fn first_due(&self) -> NaiveDate {
+        match self {
+            Recurrence::On(_) | Recurrence::MonthlyOn(_) => {
+                self.next_after(today() - Days::new(1)).unwrap_or_else(today)
+            }
+            _ => today(),
+        }
+    }
+}
+
+impl fmt::Display for Recurrence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Recurrence::Days(1) => write!(f, "every day"),
+            Recurrence::Days(n) => write!(f, "every {} days", n),
+            Recurrence::Weeks(1) => write!(f, "every week"),
+            Recurrence::Weeks(n) => write!(f, "every {} weeks", n),
+            Recurrence::Months(1) => write!(f, "every month"),
+            Recurrence::Months(n) => write!(f, "every {} months", n),
+            Recurrence::On(weekday) => write!(f, "every {}", weekday_name(weekday)),
+            Recurrence::MonthlyOn(day) => write!(f, "monthly on {}", ordinal(day)),
+        }
+    }
+}
+
+fn parse_unit(n: u32, unit: &str) -> Option<Recurrence> {
+    match unit {
+        "day" | "days" => Some(Recurrence::Days(n)),
+        "week" | "weeks" => Some(Recurrence::Weeks(n)),
+        "month" | "months" => Some(Recurrence::Months(n)),
+        _ if n == 1 => parse_weekday(unit).map(Recurrence::On),
+        _ => None,
+    }
+}
+
+fn parse_day_of_month(day: &str) -> Option<u32> {
+    let digits = day.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    digits.parse().ok().filter(|d| (1..=31).contains(d))
+}
+
+fn parse_weekday(name: &str) -> Option<Weekday> {
+    match name {
+        "monday" | "mon" => Some(Weekday::Mon),
+        "tuesday" | "tue" | "tues" => Some(Weekday::Tue),
+        "wednesday" | "wed" => Some(Weekday::Wed),
+        "thursday" | "thu" | "thurs" => Some(Weekday::Thu),
+        "friday" | "fri" => Some(Weekday::Fri),
+        "saturday" | "sat" => Some(Weekday::Sat),
+        "sunday" | "sun" => Some(Weekday::Sun),
+        _ => None,
+    }
+}
+
+fn weekday_name(weekday: Weekday) -> &'static str {
+    match weekday {
+        Weekday::Mon => "monday",
+        Weekday::Tue => "tuesday",
+        Weekday::Wed => "wednesday",
+        Weekday::Thu => "thursday",
+        Weekday::Fri => "friday",
+        Weekday::Sat => "saturday",
+        Weekday::Sun => "sunday",
+    }
+}
+
+fn ordinal(n: u32) -> String {
+    let suffix = match (n % 10, n % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    };
+    format!("{}{}", n, suffix)
+}
+
+/// `day` of the given month, or the month's last day if it is shorter.
+fn clamp_to_month(year: i32, month: u32, day: u32) -> NaiveDate {
+    (1..=day)
+        .rev()
+        .find_map(|d| NaiveDate::from_ymd_opt(year, month, d))
+        .unwrap()
+}
+
+pub fn today() -> NaiveDate {
+    Local::now().date_naive()
+}
+
+/// Parses `YYYY-MM-DD`, `today`, `tomorrow` or a weekday name (meaning its
+/// next occurrence).
+pub fn parse_date(s: &str) -> Option<NaiveDate> {
+    let s = s.trim().to_lowercase();
+    match s.as_str() {
+        "today" => Some(today()),
+        "tomorrow" => Some(today() + Days::new(1)),
+        _ => NaiveDate::parse_from_str(&s, "%Y-%m-%d")
+            .ok()
+            .or_else(|| parse_weekday(&s).and_then(|w| Recurrence::On(w).next_after(today()))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    #[test]
+    fn parses_rules() {
+        assert_eq!(Recurrence::parse("daily"), Some(Recurrence::Days(1)));
+        assert_eq!(Recurrence::parse("every 2 weeks"), Some(Recurrence::Weeks(2)));
+        assert_eq!(Recurrence::parse("Every Monday"), Some(Recurrence::On(Weekday::Mon)));
+        assert_eq!(Recurrence::parse("monthly on the 31st"), Some(Recurrence::MonthlyOn(31)));
+        assert_eq!(Recurrence::parse("month"), Some(Recurrence::Months(1)));
+        assert_eq!(Recurrence::parse("every 0 days"), None);
+        assert_eq!(Recurrence::parse("every 2 mondays"), None);
+        assert_eq!(Recurrence::parse("monthly on 32nd"), None);
+        assert_eq!(Recurrence::parse("every 4294967295 months"), None);
+    }
+
+    #[test]
+    fn display_parses_back() {
+        for rule in ["every day", "every 3 days", "every week", "every 2 months", "every friday", "monthly on 22nd"] {
+            let recur = Recurrence::parse(rule).unwrap();
+            assert_eq!(recur.to_string(), rule);
+            assert_eq!(Recurrence::parse(&recur.to_string()), Some(recur));
+        }
+    }
+
+    #[test]
+    fn next_after_steps_by_interval() {
+        assert_eq!(Recurrence::Days(3).next_after(date(2024, 2, 27)), Some(date(2024, 3, 1)));
+        assert_eq!(Recurrence::Weeks(2).next_after(date(2024, 1, 1)), Some(date(2024, 1, 15)));
+        // Months keep the day where they can and clamp where they can't
+        assert_eq!(Recurrence::Months(1).next_after(date(2024, 1, 31)), Some(date(2024, 2, 29)));
+        assert_eq!(Recurrence::Months(12).next_after(date(2023, 5, 10)), Some(date(2024, 5, 10)));
+    }
+
+    #[test]
+    fn next_after_weekday_is_strictly_later() {
+        // 2024-01-01 was a Monday
+        let monday = Recurrence::On(Weekday::Mon);
+        assert_eq!(monday.next_after(date(2024, 1, 1)), Some(date(2024, 1, 8)));
+        assert_eq!(monday.next_after(date(2024, 1, 3)), Some(date(2024, 1, 8)));
+        assert_eq!(Recurrence::On(Weekday::Sun).next_after(date(2024, 1, 1)), Some(date(2024, 1, 7)));
+    }
+
+    #[test]
+    fn next_after_day_of_month() {
+        let on_31st = Recurrence::MonthlyOn(31);
+        assert_eq!(on_31st.next_after(date(2024, 1, 15)), Some(date(2024, 1, 31)));
+        assert_eq!(on_31st.next_after(date(2024, 1, 31)), Some(date(2024, 2, 29)));
+        assert_eq!(on_31st.next_after(date(2024, 2, 29)), Some(date(2024, 3, 31)));
+        assert_eq!(Recurrence::MonthlyOn(1).next_after(date(2024, 12, 1)), Some(date(2025, 1, 1)));
+    }
+
+    #[test]
+    fn next_after_out_of_range_is_none() {
+        assert_eq!(Recurrence::Days(1).next_after(NaiveDate::MAX), None);
+        assert_eq!(Recurrence::Months(1).next_after(NaiveDate::MAX), None);
+        assert_eq!(Recurrence::On(Weekday::Mon).next_after(NaiveDate::MAX), None);
+        assert_eq!(Recurrence::MonthlyOn(1).next_after(NaiveDate::MAX), None);
+        assert_eq!(Recurrence::Days(1).next_due(Some(NaiveDate::MAX)), None);
+    }
+
+    #[test]
+    fn next_due_skips_past_occurrences() {
+        let next = Recurrence::Days(1).next_due(Some(date(2000, 1, 1))).unwrap();
+        assert_eq!(next, today() + Days::new(1));
+        assert!(Recurrence::Weeks(1).next_due(None).unwrap() > today());
+    }
+
+    #[test]
+    fn first_due_is_today_or_later() {
+        assert_eq!(Recurrence::Days(2).first_due(), today());
+        let friday = Recurrence::On(Weekday::Fri).first_due();
+        assert_eq!(friday.weekday(), Weekday::Fri);
+        assert!(friday >= today() && friday < today() + Days::new(7));
+    }
+}
@@ -0,0 +1,47 @@
+// repo.rs
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Branch tags are written `branch:<name>`; git doesn't allow `:` in
+/// branch names, so they can't be mistaken for anything else.
+const BRANCH_PREFIX: &str = "branch:";
+
+/// The git checkout a directory belongs to.
+pub struct Context {
+    pub root: PathBuf,
+    /// `None` when HEAD is detached.
+    pub branch: Option<String>,
+}
+
+fn git(dir: &Path, args: &[&str]) -> Option<String> {
+    let output = Command::new("git").arg("-C").arg(dir).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// The checkout `dir` is in, if it's in one.
+pub fn context(dir: &Path) -> Option<Context> {
+    let root = git(dir, &["rev-parse", "--show-toplevel"])?;
+    let branch = git(dir, &["symbolic-ref", "--short", "-q", "HEAD"]);
+    Some(Context { root: PathBuf::from(root), branch })
+}
+
+/// Whether git accepts `name` as a branch name. Branch tags come from list
+/// files that may be shared, so they're checked before being used.
+pub fn is_valid_branch(name: &str) -> bool {
+    Command::new("git")
+        .args(["check-ref-format", "--branch", name])
+        .output()
+        .is_ok_and(|output| output.status.success())
+}
+
+pub fn branch_tag(branch: &str) -> PathBuf {
+    PathBuf::from(format!("{}{}", BRANCH_PREFIX, branch))
+}
+
+pub fn parse_branch(tag: &Path) -> Option<String> {
+    tag.to_str()?.strip_prefix(BRANCH_PREFIX).map(str::to_string)
+}
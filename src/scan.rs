@@ -0,0 +1,222 @@
+// scan.rs
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use ignore::WalkBuilder;
+
+use crate::comments::{self, Comment};
+use crate::item::{self, TodoItem};
+use crate::TodoApp;
+
+/// The comments that become tasks.
+const KEYWORDS: &[&str] = &["TODO", "FIXME", "HACK"];
+
+/// Extensions of the source files worth looking in. Other files, such as
+/// Markdown with its `# TODO` headings, would mostly give false alarms.
+const SOURCE_EXTENSIONS: &[&str] = &[
+    "rs", "c", "h", "cc", "cpp", "cxx", "hpp", "hh", "m", "mm", "cs", "java", "kt", "kts", "scala",
+    "swift", "go", "dart", "zig", "js", "jsx", "mjs", "cjs", "ts", "tsx", "vue", "svelte", "css",
+    "scss", "less", "html", "xml", "py", "rb", "php", "pl", "pm", "lua", "sh", "bash", "zsh", "fish",
+    "sql", "hs", "elm", "ml", "ex", "exs", "erl", "clj", "el", "lisp", "r", "jl", "tex", "toml",
+    "yaml", "yml", "cmake", "nix", "tf",
+];
+
+/// Source files known by name rather than extension.
+const SOURCE_NAMES: &[&str] = &["Makefile", "Dockerfile", "CMakeLists.txt", "Rakefile", "Gemfile"];
+
+fn is_source(path: &Path) -> bool {
+    let by_name = path.file_name().and_then(|n| n.to_str()).is_some_and(|n| SOURCE_NAMES.contains(&n));
+    let by_extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| SOURCE_EXTENSIONS.contains(&e.to_lowercase().as_str()));
+    by_name || by_extension
+}
+
+/// The TODO comments of every source file under `dir` that git wouldn't
+/// ignore, in path order.
+fn find_comments(dir: &Path) -> Vec<(PathBuf, Comment)> {
+    let mut files = Vec::new();
+    // Honor .gitignore files even when `dir` isn't a git checkout
+    for entry in WalkBuilder::new(dir).require_git(false).build() {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                println!("Warning: {}", e);
+                continue;
+            }
+        };
+        if entry.file_type().is_some_and(|t| t.is_file()) && is_source(entry.path()) {
+            files.push(entry.into_path());
+        }
+    }
+    files.sort();
+    comments_in(&files)
+}
+
+/// The TODO comments in `files`, skipping any that can't be read, are
+/// binary or aren't UTF-8.
+fn comments_in(files: &[PathBuf]) -> Vec<(PathBuf, Comment)> {
+    let mut found = Vec::new();
+    for path in files {
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                println!("Warning: Could not read {}: {}", path.display(), e);
+                continue;
+            }
+        };
+        // Binary and non-UTF-8 files are skipped
+        let source = match String::from_utf8(bytes) {
+            Ok(source) if !source.contains('\0') => source,
+            _ => continue,
+        };
+        for comment in comments::find(&source, KEYWORDS) {
+            found.push((path.clone(), comment));
+        }
+    }
+    found
+}
+
+/// Where a task found by an earlier scan of `dir` came from.
+fn location(item: &TodoItem, dir: &Path) -> Option<(PathBuf, usize)> {
+    item.tags
+        .iter()
+        .filter_map(|tag| comments::parse_location(tag))
+        .find(|(file, _)| file.starts_with(dir))
+}
+
+impl TodoApp {
+    /// Keeps `list_name` in step with the TODO comments under `dir`: new
+    /// comments are added as tasks tagged with their `file:line`, tasks
+    /// follow their comment when it moves or is reworded, and tasks whose
+    /// comment has gone are marked done.
+    pub(crate) fn scan(&self, dir: &str, list_name: &str) -> io::Result<()> {
+        if !Self::check_new_list_name(list_name) {
+            return Ok(());
+        }
+        let dir = match fs::canonicalize(dir) {
+            Ok(dir) if dir.is_dir() => dir,
+            _ => {
+                println!("Error: '{}' is not a directory", dir);
+                return Ok(());
+            }
+        };
+
+        let found = find_comments(&dir);
+        let mut items = self.read_items(list_name)?;
+        let locations: Vec<Option<(PathBuf, usize)>> = items.iter().map(|item| location(item, &dir)).collect();
+        let mut claimed = vec![false; items.len()];
+        let mut matches: Vec<Option<usize>> = vec![None; found.len()];
+
+        // A comment belongs to the task with its text in the same file,
+        // the closest one if there are several...
+        for (slot, (path, comment)) in matches.iter_mut().zip(&found) {
+            *slot = (0..items.len())
+                .filter(|&i| !claimed[i] && items[i].text == comment.text)
+                .filter_map(|i| match &locations[i] {
+                    Some((file, line)) if file == path => Some((line.abs_diff(comment.line), i)),
+                    _ => None,
+                })
+                .min()
+                .map(|(_, i)| i);
+            if let Some(i) = *slot {
+                claimed[i] = true;
+            }
+        }
+        // ...or else to the task on its line, when it has been reworded
+        for (slot, (path, comment)) in matches.iter_mut().zip(&found) {
+            if slot.is_none() {
+                *slot = (0..items.len()).find(|&i| {
+                    !claimed[i] && locations[i].as_ref() == Some(&(path.clone(), comment.line))
+                });
+                if let Some(i) = *slot {
+                    claimed[i] = true;
+                }
+            }
+        }
+
+        let (mut added, mut updated, mut closed) = (0, 0, 0);
+        for (slot, (path, comment)) in matches.iter().zip(&found) {
+            let tag = comments::location_tag(path, comment.line);
+            let i = match *slot {
+                Some(i) => i,
+                None => {
+                    let mut task = TodoItem::new(comment.text.clone());
+                    task.add_tag(tag);
+                    items.push(task);
+                    added += 1;
+                    continue;
+                }
+            };
+            let item = &mut items[i];
+            if item.text == comment.text && !item.done && item.tags.contains(&tag) {
+                continue;
+            }
+            // A comment that's back reopens its task
+            item.text = comment.text.clone();
+            item.done = false;
+            item.tags.retain(|t| comments::parse_location(t).is_none_or(|(file, _)| !file.starts_with(&dir)));
+            item.add_tag(tag);
+            updated += 1;
+        }
+        for i in 0..claimed.len() {
+            if locations[i].is_some() && !claimed[i] && !items[i].done {
+                let end = item::subtree_end(&items, i);
+                for item in &mut items[i..end] {
+                    item.done = true;
+                }
+                closed += 1;
+            }
+        }
+        item::roll_up(&mut items);
+
+        if added + updated + closed > 0 {
+            self.write_items(list_name, &items)?;
+        }
+        println!(
+            "Scanned {} into list '{}': {} task(s) added, {} updated, {} done",
+            dir.display(),
+            list_name,
+            added,
+            updated,
+            closed
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skips_files_it_cannot_read() {
+        let dir = std::env::temp_dir().join(format!("todo-test-scan-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("folder.rs")).unwrap();
+        fs::write(dir.join("main.rs"), "fn main() {} // TODO: handle args\n").unwrap();
+        fs::write(dir.join("blob.rs"), b"\0// TODO: not source\n").unwrap();
+        fs::write(dir.join("latin1.rs"), b"// TODO: caf\xe9\n").unwrap();
+
+        let files: Vec<PathBuf> = ["blob.rs", "folder.rs", "gone.rs", "latin1.rs", "main.rs"]
+            .iter()
+            .map(|name| dir.join(name))
+            .collect();
+        let found = comments_in(&files);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].0, dir.join("main.rs"));
+        assert_eq!(found[0].1.text, "handle args");
+
+        // Walking finds only source files, and honors .gitignore
+        fs::write(dir.join(".gitignore"), "ignored.rs\n").unwrap();
+        fs::write(dir.join("ignored.rs"), "// TODO: skip me\n").unwrap();
+        fs::write(dir.join("notes.md"), "# TODO list\n").unwrap();
+        let texts: Vec<String> = find_comments(&dir).into_iter().map(|(_, comment)| comment.text).collect();
+        assert_eq!(texts, ["handle args"]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,507 @@
+// serve.rs
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::deps::{self, Lists};
+use crate::item::{self, Priority, TodoItem};
+use crate::recur::{self, Recurrence};
+use crate::{names, TodoApp};
+
+/// The web UI, a single page that only talks to the API below.
+const INDEX_HTML: &str = include_str!("web/index.html");
+
+/// How often `/events` looks for changed list files.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// Comment lines keep idle event streams from being closed by proxies.
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// An error response: the HTTP status and a message for `{"error": ...}`.
+struct ApiError(u16, String);
+
+impl From<io::Error> for ApiError {
+    fn from(e: io::Error) -> Self {
+        ApiError(500, e.to_string())
+    }
+}
+
+type ApiResult = Result<(u16, Value), ApiError>;
+
+fn bad_request(message: impl Into<String>) -> ApiError {
+    ApiError(400, message.into())
+}
+
+fn not_found(message: impl Into<String>) -> ApiError {
+    ApiError(404, message.into())
+}
+
+fn header(request: &Request, name: &'static str) -> Option<String> {
+    request.headers().iter().find(|h| h.field.equiv(name)).map(|h| h.value.to_string())
+}
+
+/// Turns away requests that didn't come from the web UI or a local client:
+/// a Host other than our own address (as after DNS rebinding), an Origin of
+/// some other site, and changes without a JSON content type, which a
+/// cross-site form can't send.
+fn check_request(request: &Request, port: u16) -> Result<(), ApiError> {
+    let ours = [format!("127.0.0.1:{}", port), format!("localhost:{}", port)];
+    let host = header(request, "Host").unwrap_or_default();
+    if !ours.iter().any(|address| address.eq_ignore_ascii_case(&host)) {
+        return Err(ApiError(403, format!("unexpected Host '{}'", host)));
+    }
+    if let Some(origin) = header(request, "Origin") {
+        if !ours.iter().any(|address| origin.eq_ignore_ascii_case(&format!("http://{}", address))) {
+            return Err(ApiError(403, format!("requests from '{}' are not allowed", origin)));
+        }
+    }
+    if request.method() != &Method::Get {
+        let content_type = header(request, "Content-Type").unwrap_or_default();
+        let media_type = content_type.split(';').next().unwrap_or("").trim();
+        if !media_type.eq_ignore_ascii_case("application/json") {
+            return Err(ApiError(415, "Content-Type must be application/json".to_string()));
+        }
+    }
+    Ok(())
+}
+
+fn respond_json(request: Request, status: u16, body: Value) {
+    let content_type = Header::from_bytes("Content-Type", "application/json").unwrap();
+    let response = Response::from_string(body.to_string())
+        .with_status_code(status)
+        .with_header(content_type);
+    if let Err(e) = request.respond(response) {
+        eprintln!("Warning: Could not send response: {}", e);
+    }
+}
+
+/// Splits `a=1&b=2` into decoded pairs.
+fn parse_query(query: &str) -> BTreeMap<String, String> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (names::decode(&key.replace('+', " ")), names::decode(&value.replace('+', " ")))
+        })
+        .collect()
+}
+
+/// A task as the API returns it.
+fn item_json(lists: &Lists, list_name: &str, items: &[TodoItem], index: usize) -> Value {
+    let item = &items[index];
+    let parent = item::parents(items)[index].map(|p| items[p].id.clone());
+    json!({
+        "id": item.id,
+        "list": list_name,
+        "address": item::addresses(items)[index],
+        "text": item.text,
+        "done": item.done,
+        "depth": item.depth,
+        "parent": parent,
+        "priority": item.priority.map(|p| p.to_string()),
+        "due": item.due.map(|d| d.to_string()),
+        "repeat": item.recur.map(|r| r.to_string()),
+        "tags": item.tags.iter().map(|t| t.to_string_lossy().to_string()).collect::<Vec<_>>(),
+        "note": item.note,
+        "depends": item.depends.iter().map(|d| d.to_string()).collect::<Vec<_>>(),
+        "blocked": deps::is_blocked(lists, items, index),
+        "created": item.created.map(|d| d.to_string()),
+        "updated": item.updated.map(|t| t.format("%Y-%m-%dT%H:%M:%S").to_string()),
+    })
+}
+
+fn list_json(lists: &Lists, list_name: &str) -> Value {
+    let items = lists.get(list_name).map(|items| items.as_slice()).unwrap_or(&[]);
+    let tasks: Vec<Value> = (0..items.len()).map(|i| item_json(lists, list_name, items, i)).collect();
+    json!({ "name": list_name, "items": tasks })
+}
+
+/// Optional string fields may be sent as `null` to clear them.
+fn optional_str<'a>(body: &'a Value, key: &str) -> Result<Option<Option<&'a str>>, ApiError> {
+    match body.get(key) {
+        None => Ok(None),
+        Some(Value::Null) => Ok(Some(None)),
+        Some(Value::String(s)) => Ok(Some(Some(s.as_str()))),
+        Some(_) => Err(bad_request(format!("'{}' must be a string or null", key))),
+    }
+}
+
+/// Applies the fields of a POST or PATCH body that only touch the task
+/// itself: text, due, priority, repeat, note and tags.
+fn apply_fields(item: &mut TodoItem, body: &Value) -> Result<(), ApiError> {
+    if let Some(text) = optional_str(body, "text")? {
+        match text.map(str::trim) {
+            Some(text) if !text.is_empty() => item.text = text.to_string(),
+            _ => return Err(bad_request("'text' can't be empty")),
+        }
+    }
+    if let Some(due) = optional_str(body, "due")? {
+        item.due = match due {
+            None => None,
+            Some(due) => Some(recur::parse_date(due).ok_or_else(|| bad_request(format!("invalid date '{}'", due)))?),
+        };
+    }
+    if let Some(priority) = optional_str(body, "priority")? {
+        item.priority = match priority {
+            None => None,
+            Some(level) => Some(Priority::parse(level).ok_or_else(|| bad_request(format!("invalid priority '{}'", level)))?),
+        };
+    }
+    if let Some(rule) = optional_str(body, "repeat")? {
+        item.recur = match rule {
+            None => None,
+            Some(rule) => Some(Recurrence::parse(rule).ok_or_else(|| bad_request(format!("invalid rule '{}'", rule)))?),
+        };
+        if let (Some(recur), None) = (item.recur, item.due) {
+            item.due = Some(recur.first_due());
+        }
+    }
+    if let Some(note) = optional_str(body, "note")? {
+        item.note = note.filter(|note| !note.trim().is_empty()).map(str::to_string);
+    }
+    if let Some(tags) = body.get("tags") {
+        let tags = tags.as_array().ok_or_else(|| bad_request("'tags' must be an array of paths"))?;
+        item.tags.clear();
+        for tag in tags {
+            let tag = tag.as_str().ok_or_else(|| bad_request("'tags' must be an array of paths"))?;
+            item.add_tag(item::expand_path(tag));
+        }
+    }
+    Ok(())
+}
+
+/// Size and modification time of every list file, to spot changes.
+fn list_files(dir: &Path) -> BTreeMap<String, (u64, Option<SystemTime>)> {
+    let mut files = BTreeMap::new();
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            if let (Some(list_name), Ok(metadata)) = (names::list_name(&file_name), entry.metadata()) {
+                files.insert(list_name, (metadata.len(), metadata.modified().ok()));
+            }
+        }
+    }
+    files
+}
+
+/// Serves `/events`: a server-sent event named `change` whenever a list
+/// file is written, created or removed, with the list's name as data.
+/// Runs until the client goes away.
+fn stream_events(dir: PathBuf, request: Request) {
+    let mut writer = request.into_writer();
+    let head = "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: keep-alive\r\n\r\nretry: 2000\n\n";
+    if writer.write_all(head.as_bytes()).and_then(|_| writer.flush()).is_err() {
+        return;
+    }
+
+    let mut known = list_files(&dir);
+    let mut last_write = Instant::now();
+    loop {
+        thread::sleep(POLL_INTERVAL);
+        let current = list_files(&dir);
+        let changed: BTreeSet<&String> = known
+            .keys()
+            .chain(current.keys())
+            .filter(|name| known.get(*name) != current.get(*name))
+            .collect();
+        let mut message = String::new();
+        for name in changed {
+            let data = json!({ "list": name, "exists": current.contains_key(name) });
+            message.push_str(&format!("event: change\ndata: {}\n\n", data));
+        }
+        known = current;
+
+        if message.is_empty() && last_write.elapsed() >= KEEPALIVE_INTERVAL {
+            message.push_str(": keepalive\n\n");
+        }
+        if !message.is_empty() {
+            if writer.write_all(message.as_bytes()).and_then(|_| writer.flush()).is_err() {
+                return;
+            }
+            last_write = Instant::now();
+        }
+    }
+}
+
+impl TodoApp {
+    /// Runs the JSON API, and the web UI at `/`, on localhost until
+    /// interrupted. Every request reads the lists from disk, so changes made
+    /// with the command line (or by another machine through `todo sync`)
+    /// show up straight away.
+    pub(crate) fn serve(&mut self, port: u16) -> io::Result<()> {
+        let server = Server::http(("127.0.0.1", port)).map_err(|e| io::Error::other(e.to_string()))?;
+        println!("Serving {} on http://127.0.0.1:{}/ (Ctrl-C to stop)", self.base_dir.display(), port);
+
+        for mut request in server.incoming_requests() {
+            if let Err(ApiError(status, message)) = check_request(&request, port) {
+                respond_json(request, status, json!({ "error": message }));
+                continue;
+            }
+            if request.method() == &Method::Get {
+                match request.url().split('?').next() {
+                    Some("/events") => {
+                        let dir = self.base_dir.clone();
+                        thread::spawn(move || stream_events(dir, request));
+                        continue;
+                    }
+                    Some("/") | Some("/index.html") => {
+                        let content_type = Header::from_bytes("Content-Type", "text/html; charset=utf-8").unwrap();
+                        if let Err(e) = request.respond(Response::from_string(INDEX_HTML).with_header(content_type)) {
+                            eprintln!("Warning: Could not send response: {}", e);
+                        }
+                        continue;
+                    }
+                    _ => {}
+                }
+            }
+
+            let (status, body) = match self.handle(&mut request) {
+                Ok((status, body)) => (status, body),
+                Err(ApiError(status, message)) => (status, json!({ "error": message })),
+            };
+            let mutated = status < 400 && request.method() != &Method::Get;
+            if mutated {
+                self.auto_commit(&[format!("serve: {} {}", request.method(), request.url())]);
+            }
+            respond_json(request, status, body);
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, request: &mut Request) -> ApiResult {
+        let method = request.method().clone();
+        let url = request.url().to_string();
+        let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+        let query = parse_query(query);
+        let segments: Vec<String> = path
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .map(|segment| names::normalize(&names::decode(segment)))
+            .collect();
+        let segments: Vec<&str> = segments.iter().map(|s| s.as_str()).collect();
+
+        let body = if matches!(method, Method::Post | Method::Patch) {
+            let mut text = String::new();
+            request.as_reader().read_to_string(&mut text)?;
+            serde_json::from_str(&text).map_err(|e| bad_request(format!("invalid JSON: {}", e)))?
+        } else {
+            Value::Null
+        };
+
+        match (method, segments.as_slice()) {
+            (Method::Get, ["lists"]) => {
+                let lists = self.read_all_lists()?;
+                let summaries: Vec<Value> = self
+                    .list_names()?
+                    .iter()
+                    .map(|name| {
+                        let items = &lists[name];
+                        let open = items.iter().filter(|item| !item.done).count();
+                        json!({ "name": name, "tasks": items.len(), "open": open })
+                    })
+                    .collect();
+                Ok((200, json!(summaries)))
+            }
+            (Method::Get, ["lists", list_name]) | (Method::Get, ["lists", list_name, "items"]) => {
+                if !self.get_list_path(list_name).exists() {
+                    return Err(not_found(format!("list '{}' not found", list_name)));
+                }
+                Ok((200, list_json(&self.read_all_lists()?, list_name)))
+            }
+            (Method::Post, ["lists", list_name, "items"]) => self.api_create(list_name, &body),
+            (Method::Get, ["lists", list_name, "items", id]) => {
+                let lists = self.read_all_lists()?;
+                let items = lists.get(*list_name).ok_or_else(|| not_found(format!("list '{}' not found", list_name)))?;
+                let index = items
+                    .iter()
+                    .position(|item| item.id == *id)
+                    .ok_or_else(|| not_found(format!("task '{}' not found", id)))?;
+                Ok((200, item_json(&lists, list_name, items, index)))
+            }
+            (Method::Patch, ["lists", list_name, "items", id]) => self.api_update(list_name, id, &body),
+            (Method::Delete, ["lists", list_name, "items", id]) => self.api_remove(list_name, id),
+            (Method::Get, ["tags"]) => {
+                // Same matching as `todo here`: the path, anything beneath it
+                // or any of its parents
+                let path = query.get("path").ok_or_else(|| bad_request("missing ?path="))?;
+                let path = item::expand_path(path);
+                self.api_filter(|item| item.tags.iter().any(|tag| tag.starts_with(&path) || path.starts_with(tag)))
+            }
+            (Method::Get, ["search"]) => {
+                let q = query.get("q").ok_or_else(|| bad_request("missing ?q="))?.to_lowercase();
+                let open_only = query.get("done").is_some_and(|done| done == "false");
+                self.api_filter(|item| {
+                    let matches = item.text.to_lowercase().contains(&q)
+                        || item.note.as_ref().is_some_and(|note| note.to_lowercase().contains(&q));
+                    matches && !(open_only && item.done)
+                })
+            }
+            _ => Err(not_found(format!("no such endpoint: {}", path))),
+        }
+    }
+
+    /// Every task, across all lists, for which `keep` holds.
+    fn api_filter(&self, keep: impl Fn(&TodoItem) -> bool) -> ApiResult {
+        let lists = self.read_all_lists()?;
+        let mut found = Vec::new();
+        for (list_name, items) in &lists {
+            for (index, item) in items.iter().enumerate() {
+                if keep(item) {
+                    found.push(item_json(&lists, list_name, items, index));
+                }
+            }
+        }
+        Ok((200, json!(found)))
+    }
+
+    /// `POST /lists/:name/items`: adds a task, under `parent` (a task ID) if
+    /// given. Creates the list if needed.
+    fn api_create(&self, list_name: &str, body: &Value) -> ApiResult {
+        names::validate(list_name).map_err(|e| bad_request(format!("invalid list name: {}", e)))?;
+        if body.get("text").and_then(Value::as_str).is_none() {
+            return Err(bad_request("'text' is required"));
+        }
+
+        let mut items = self.read_items(list_name)?;
+        let mut new_item = TodoItem::new(String::new());
+        apply_fields(&mut new_item, body)?;
+
+        let index = match body.get("parent").and_then(Value::as_str) {
+            Some(parent_id) => {
+                let parent = items
+                    .iter()
+                    .position(|item| item.id == parent_id)
+                    .ok_or_else(|| bad_request(format!("parent '{}' not found", parent_id)))?;
+                new_item.depth = items[parent].depth + 1;
+                let end = item::subtree_end(&items, parent);
+                items.insert(end, new_item);
+                end
+            }
+            None => {
+                items.push(new_item);
+                items.len() - 1
+            }
+        };
+        let id = items[index].id.clone();
+        item::roll_up(&mut items);
+        self.write_items(list_name, &items)?;
+
+        let lists = self.read_all_lists()?;
+        let items = &lists[list_name];
+        let index = items.iter().position(|item| item.id == id).unwrap_or(index);
+        Ok((201, item_json(&lists, list_name, items, index)))
+    }
+
+    /// `PATCH /lists/:name/items/:id`: besides the task's own fields, takes
+    /// `done` (applied to subtasks too, as with `todo done`), `position`
+    /// (among its siblings, from 1) and `list` (moves it with its subtasks).
+    fn api_update(&mut self, list_name: &str, id: &str, body: &Value) -> ApiResult {
+        if !self.get_list_path(list_name).exists() {
+            return Err(not_found(format!("list '{}' not found", list_name)));
+        }
+        let mut items = self.read_items(list_name)?;
+        let mut index = items
+            .iter()
+            .position(|item| item.id == id)
+            .ok_or_else(|| not_found(format!("task '{}' not found", id)))?;
+        let before = items.clone();
+
+        apply_fields(&mut items[index], body)?;
+        if let Some(done) = body.get("done") {
+            let done = done.as_bool().ok_or_else(|| bad_request("'done' must be true or false"))?;
+            let end = item::subtree_end(&items, index);
+            for item in &mut items[index..end] {
+                item.done = done;
+            }
+            item::roll_up(&mut items);
+            item::spawn_recurrences(&before, &mut items);
+        }
+        if let Some(position) = body.get("position") {
+            let position = position
+                .as_u64()
+                .filter(|&p| p > 0)
+                .ok_or_else(|| bad_request("'position' must be a number from 1"))?;
+            // A saved sort would undo the move on the next write
+            if self.saved_sort(list_name).is_some() {
+                self.config.unset(&Self::sort_setting(list_name))?;
+            }
+            index = item::move_to_position(&mut items, index, position as usize);
+        }
+
+        let target = match body.get("list") {
+            None => None,
+            Some(Value::String(target)) if names::normalize(target) == list_name => None,
+            Some(Value::String(target)) => {
+                let target = names::normalize(target);
+                names::validate(&target).map_err(|e| bad_request(format!("invalid list name: {}", e)))?;
+                Some(target)
+            }
+            Some(_) => return Err(bad_request("'list' must be a list name")),
+        };
+
+        match target {
+            None => self.write_items(list_name, &items)?,
+            Some(ref target) => {
+                let end = item::subtree_end(&items, index);
+                let base_depth = items[index].depth;
+                let mut moved: Vec<TodoItem> = items.drain(index..end).collect();
+                for item in &mut moved {
+                    item.depth -= base_depth;
+                }
+                item::roll_up(&mut items);
+
+                let mut destination = self.read_items(target)?;
+                destination.extend(moved.iter().cloned());
+                item::roll_up(&mut destination);
+
+                // Dependencies on the moved tasks now point at their new list
+                let moved_ids: Vec<String> = moved.iter().map(|item| item.id.clone()).collect();
+                let mut lists = self.read_all_lists()?;
+                lists.insert(list_name.to_string(), items);
+                lists.insert(target.clone(), destination);
+                lists.retain(|name, items| {
+                    deps::retarget(items, list_name, target, Some(&moved_ids)) || name == list_name || name == target
+                });
+                let writes: Vec<(&str, &[TodoItem])> = lists
+                    .iter()
+                    .map(|(name, items)| (name.as_str(), items.as_slice()))
+                    .collect();
+                self.write_lists(&writes)?;
+            }
+        }
+
+        let list_name = target.as_deref().unwrap_or(list_name);
+        let lists = self.read_all_lists()?;
+        let items = &lists[list_name];
+        let index = items
+            .iter()
+            .position(|item| item.id == id)
+            .ok_or_else(|| not_found(format!("task '{}' not found", id)))?;
+        Ok((200, item_json(&lists, list_name, items, index)))
+    }
+
+    /// `DELETE /lists/:name/items/:id`: removes a task and its subtasks.
+    fn api_remove(&self, list_name: &str, id: &str) -> ApiResult {
+        if !self.get_list_path(list_name).exists() {
+            return Err(not_found(format!("list '{}' not found", list_name)));
+        }
+        let mut items = self.read_items(list_name)?;
+        let index = items
+            .iter()
+            .position(|item| item.id == id)
+            .ok_or_else(|| not_found(format!("task '{}' not found", id)))?;
+        let end = item::subtree_end(&items, index);
+        let removed: Vec<TodoItem> = items.drain(index..end).collect();
+        item::roll_up(&mut items);
+        self.write_items(list_name, &items)?;
+        self.record_deleted(&removed)?;
+        Ok((200, json!({ "removed": removed.iter().map(|item| &item.id).collect::<Vec<_>>() })))
+    }
+}
@@ -0,0 +1,201 @@
+// sync.rs
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::{Command, Output};
+
+use crate::backup::BACKUP_DIR;
+use crate::caldav::CALDAV_STATE_DIR;
+use crate::config::CONFIG_FILE;
+use crate::doctor::DOCTOR_BACKUP_DIR;
+use crate::merge::{self, TOMBSTONE_FILE};
+use crate::TodoApp;
+
+/// What `todo sync init` adds to `base_dir`; doctor leaves these alone.
+pub const GIT_FILES: &[&str] = &[".git", ".gitignore", ".gitattributes"];
+
+const REMOTE: &str = "origin";
+
+/// The merge driver git runs for list files during `todo sync`: merges
+/// `base`, `ours` and `theirs` by task ID and leaves the result in `ours`.
+pub fn merge_driver(base: &str, ours: &str, theirs: &str) -> io::Result<()> {
+    let merged = merge::three_way(
+        &merge::read_file(Path::new(base))?,
+        &merge::read_file(Path::new(ours))?,
+        &merge::read_file(Path::new(theirs))?,
+    );
+    let mut contents = String::new();
+    for item in &merged {
+        contents.push_str(&format!("{}\n", item));
+    }
+    fs::write(ours, contents)
+}
+
+impl TodoApp {
+    fn is_synced(&self) -> bool {
+        self.base_dir.join(".git").exists()
+    }
+
+    /// Runs git inside `base_dir`. Commits and merges fall back to a generic
+    /// identity when the user hasn't configured one.
+    fn git(&self, args: &[&str]) -> io::Result<Output> {
+        let mut command = Command::new("git");
+        // Inside a git hook these point at the project being committed
+        command.env_remove("GIT_DIR").env_remove("GIT_WORK_TREE").env_remove("GIT_INDEX_FILE");
+        command.arg("-C").arg(&self.base_dir);
+        if matches!(args.first(), Some(&"commit" | &"merge")) {
+            let has_identity = Command::new("git")
+                .arg("-C")
+                .arg(&self.base_dir)
+                .args(["config", "user.email"])
+                .output()
+                .is_ok_and(|output| output.status.success());
+            if !has_identity {
+                command.args(["-c", "user.name=todo", "-c", "user.email=todo@localhost"]);
+            }
+        }
+        command.args(args).output()
+    }
+
+    /// Like `git`, but turns a failing command into an error carrying its
+    /// output.
+    fn git_checked(&self, args: &[&str]) -> io::Result<String> {
+        let output = self.git(args)?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let message = if stderr.trim().is_empty() { stdout } else { stderr };
+            return Err(io::Error::other(format!("git {}: {}", args.join(" "), message.trim())));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    /// Keeps local state out of git and has list files merged by task ID.
+    pub(crate) fn write_git_files(&self) -> io::Result<()> {
+        // A project's list settings stay on this machine
+        fs::write(
+            self.base_dir.join(".gitignore"),
+            format!("{}/\n{}/\n{}/\n{}\n*.tmp\n", BACKUP_DIR, CALDAV_STATE_DIR, DOCTOR_BACKUP_DIR, CONFIG_FILE),
+        )?;
+        // Deletion records only ever grow, so concurrent additions just combine
+        fs::write(
+            self.base_dir.join(".gitattributes"),
+            format!("*.txt merge=todo\n{} merge=union\n", TOMBSTONE_FILE),
+        )
+    }
+
+    /// Points git at this binary for merging list files. Refreshed on every
+    /// sync so it keeps working if the binary moves.
+    pub(crate) fn install_merge_driver(&self) -> io::Result<()> {
+        let exe = env::current_exe()?;
+        let driver = format!("\"{}\" sync merge-driver %O %A %B", exe.display());
+        self.git_checked(&["config", "merge.todo.name", "todo list merge by task ID"])?;
+        self.git_checked(&["config", "merge.todo.driver", &driver])?;
+        Ok(())
+    }
+
+    /// Commits whatever changed in `base_dir`, if anything did. Returns
+    /// whether a commit was made.
+    fn commit_all(&self, message: &str) -> io::Result<bool> {
+        self.git_checked(&["add", "-A"])?;
+        if self.git_checked(&["status", "--porcelain"])?.is_empty() {
+            return Ok(false);
+        }
+        self.git_checked(&["commit", "-q", "-m", message])?;
+        Ok(true)
+    }
+
+    /// Called after every command: once `base_dir` is a git repository,
+    /// each change is committed with the command that made it as message.
+    /// Set `sync.autocommit = false` to only commit when syncing.
+    pub(crate) fn auto_commit(&self, args: &[String]) {
+        if !self.is_synced() || self.config.get("sync.autocommit") == Some("false") {
+            return;
+        }
+        let message = format!("todo {}", args.join(" "));
+        if let Err(e) = self.commit_all(&message) {
+            eprintln!("Warning: Could not commit changes: {}", e);
+        }
+    }
+
+    pub(crate) fn sync_init(&mut self, remote: Option<&str>) -> io::Result<()> {
+        if self.project {
+            println!("Error: Project lists are shared through the project's own repository.");
+            return Ok(());
+        }
+        if !self.is_synced() {
+            self.git_checked(&["init", "-q"])?;
+        }
+        self.write_git_files()?;
+        self.install_merge_driver()?;
+        // Reading gives every task an ID, which merges are keyed by
+        self.read_all_lists()?;
+        self.commit_all("todo sync init")?;
+
+        if let Some(remote) = remote {
+            self.config.set("sync.remote", remote)?;
+        }
+        println!("Tracking {} with git.", self.base_dir.display());
+        match self.config.get("sync.remote") {
+            Some(remote) => println!("Run 'todo sync' to sync with {}", remote),
+            None => println!("Set a remote with 'todo config sync.remote <url>', then run 'todo sync'."),
+        }
+        Ok(())
+    }
+
+    /// Commits local changes, merges in the remote's and pushes the result.
+    /// List files are merged by task ID (see `merge::three_way`), so edits
+    /// from different machines combine instead of conflicting.
+    pub(crate) fn sync(&self) -> io::Result<()> {
+        if !self.is_synced() {
+            println!("Error: {} is not synced yet; run 'todo sync init [<remote>]' first", self.base_dir.display());
+            return Ok(());
+        }
+        let remote = match self.config.get("sync.remote") {
+            Some(remote) => remote,
+            None => {
+                println!("Error: No remote set; run 'todo config sync.remote <url>'");
+                return Ok(());
+            }
+        };
+
+        self.install_merge_driver()?;
+        self.commit_all("todo sync: local changes")?;
+
+        if self.git(&["remote", "get-url", REMOTE])?.status.success() {
+            self.git_checked(&["remote", "set-url", REMOTE, remote])?;
+        } else {
+            self.git_checked(&["remote", "add", REMOTE, remote])?;
+        }
+        let branch = self.git_checked(&["symbolic-ref", "--short", "HEAD"])?;
+        let tracking = format!("{}/{}", REMOTE, branch);
+
+        self.git_checked(&["fetch", "-q", REMOTE])?;
+        let remote_exists = self.git(&["rev-parse", "--verify", "-q", &tracking])?.status.success();
+        if remote_exists {
+            let before = self.git_checked(&["rev-parse", "HEAD"])?;
+            let merge = self.git(&["merge", "-q", "--no-edit", "--allow-unrelated-histories", &tracking])?;
+            if !merge.status.success() {
+                let _ = self.git(&["merge", "--abort"]);
+                println!("Error: Could not merge changes from {}:", remote);
+                for output in [&merge.stdout, &merge.stderr] {
+                    let output = String::from_utf8_lossy(output);
+                    if !output.trim().is_empty() {
+                        println!("{}", output.trim());
+                    }
+                }
+                println!("Nothing was changed locally; fix the conflict in {} and sync again.", self.base_dir.display());
+                return Ok(());
+            }
+            if self.git_checked(&["rev-parse", "HEAD"])? != before {
+                println!("Merged changes from {}", remote);
+            }
+        }
+
+        self.git_checked(&["push", "-q", "-u", REMOTE, &branch])?;
+        println!("Synced with {}", remote);
+        Ok(())
+    }
+}